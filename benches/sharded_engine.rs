@@ -0,0 +1,66 @@
+// Throughput of `ShardedEngine` as shards are added, against a plain `Engine`.
+//
+//     cargo bench --bench sharded_engine
+//
+// Scaling is bounded by the cores available, which the output reports: shards share only their
+// published sums, so each added core runs another shard. On one core the only gain is that each
+// shard scans a smaller window for repeats.
+use inverse_pairs::{Engine, Event, ShardedEngine};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const EVENTS: usize = 400_000;
+const DISTINCT_IDS: usize = 50_000;
+const BATCH: usize = 10_000;
+const CAPACITY: usize = 64;
+
+fn events() -> Vec<Event> {
+    let pool: Vec<Uuid> = (0..DISTINCT_IDS).map(|_| Uuid::new_v4()).collect();
+    // a fixed stride mixes first sightings with repeats
+    (0..EVENTS)
+        .map(|i| Event::new(pool[i * 7_919 % DISTINCT_IDS]))
+        .collect()
+}
+
+fn report(name: &str, elapsed: Duration, baseline: Option<Duration>) {
+    let rate = EVENTS as f64 / elapsed.as_secs_f64();
+    match baseline {
+        Some(baseline) => println!(
+            "{:<12} {:>12.0} events/s  {:>5.2}x",
+            name,
+            rate,
+            baseline.as_secs_f64() / elapsed.as_secs_f64()
+        ),
+        None => println!("{:<12} {:>12.0} events/s", name, rate),
+    }
+}
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+    println!("{} events, {} cores", EVENTS, cores);
+    let events = events();
+
+    let mut engine = Engine::new(CAPACITY);
+    let start = Instant::now();
+    for event in &events {
+        engine.ingest(event).unwrap();
+    }
+    let baseline = start.elapsed();
+    report("engine", baseline, None);
+
+    for shards in [1, 2, 4, 8, 16] {
+        let sharded = ShardedEngine::new(shards, CAPACITY);
+        let start = Instant::now();
+        for batch in events.chunks(BATCH) {
+            for result in sharded.ingest_batch(batch.to_vec()) {
+                result.unwrap();
+            }
+        }
+        report(
+            &format!("{} shards", shards),
+            start.elapsed(),
+            Some(baseline),
+        );
+    }
+}
@@ -0,0 +1,653 @@
+use inverse_pairs::{Engine, Event, TorusPoint};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const USAGE: &str = "usage: inverse-pairs-server [--bind ADDRESS] [--capacity N]
+
+Serves one in-process engine over HTTP/JSON on a loopback address (127.0.0.1:8080 by default):
+
+  POST /events                    {\"id\", \"timestamp\"?, \"weight\"?, \"payload\"?} -> placement
+  GET  /events/ID                 the id's current placement
+  GET  /average                   {\"flee_average\": [x, y] or null}
+  GET  /nearest?x=&y=[&k=][&of=]  the k (default 5) nearest follow (default) or flee points
+
+A request must arrive whole within 10 seconds, with at most 8 KiB of request line and headers.
+At most 64 connections are served at once; any more are answered with 503.";
+
+// bodies are single events, so anything larger is a mistake
+const MAX_BODY: usize = 64 * 1024;
+// the request line and headers together
+const MAX_HEAD: usize = 8 * 1024;
+// for the whole request and for each write, so a stalled or trickling client cannot hold a
+// thread for long
+const TIMEOUT: Duration = Duration::from_secs(10);
+// each connection has a thread, so this bounds the threads too
+const MAX_CONNECTIONS: usize = 64;
+const DEFAULT_NEIGHBOURS: usize = 5;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    bind: SocketAddr,
+    capacity: usize,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+        capacity: 64,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-b" | "--bind" => {
+                let value = args.next().ok_or("--bind needs a value")?;
+                options.bind = value
+                    .parse()
+                    .map_err(|_| format!("invalid address: {}", value))?;
+            }
+            "-c" | "--capacity" => {
+                let value = args.next().ok_or("--capacity needs a value")?;
+                options.capacity = match value.parse() {
+                    Ok(capacity) if capacity > 0 => capacity,
+                    _ => return Err(format!("invalid capacity: {}", value)),
+                };
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+
+    // the service has no authentication, so it is only ever reachable from this machine
+    if !options.bind.ip().is_loopback() {
+        return Err(format!("{} is not a loopback address", options.bind.ip()));
+    }
+    Ok(options)
+}
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Response {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+// A stream whose reads all have to finish by one deadline, however the bytes are spread out; a
+// read after it fails with `TimedOut`.
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "the request deadline passed",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+// A read that timed out is answered with 408, anything else that fails with 400.
+fn read_failed(error: io::Error) -> Response {
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+            Response::error(408, "timed out waiting for the request")
+        }
+        _ => Response::error(400, format!("unreadable request: {}", error)),
+    }
+}
+
+// Reads one line of the request head, at most `budget` bytes of which are left.
+fn read_head_line(reader: &mut impl BufRead, budget: &mut usize) -> Result<String, Response> {
+    let mut line = String::new();
+    let read = reader
+        .by_ref()
+        .take(*budget as u64)
+        .read_line(&mut line)
+        .map_err(read_failed)?;
+    *budget -= read;
+    if !line.ends_with('\n') {
+        return Err(if *budget == 0 {
+            Response::error(431, "request line and headers are too large")
+        } else {
+            Response::error(400, "the request ended early")
+        });
+    }
+    Ok(line)
+}
+
+// Reads one HTTP/1.1 request; only Content-Length bodies are understood.
+fn read_request(reader: &mut impl BufRead) -> Result<Request, Response> {
+    let bad_request = |message: &str| Response::error(400, message);
+    let mut budget = MAX_HEAD;
+    let line = read_head_line(reader, &mut budget)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(bad_request("malformed request line")),
+    };
+
+    let mut length = 0;
+    loop {
+        let header = read_head_line(reader, &mut budget)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| bad_request("invalid Content-Length"))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(Response::error(413, "request body is too large"));
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|error| match error.kind() {
+            ErrorKind::UnexpectedEof => {
+                bad_request("request body is shorter than its Content-Length")
+            }
+            _ => read_failed(error),
+        })?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (pair.to_string(), String::new()),
+        })
+        .collect();
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body,
+    })
+}
+
+fn write_response(output: &mut impl Write, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Unprocessable Entity",
+    };
+    let body = response.body.to_string();
+    write!(
+        output,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        body.len(),
+        body
+    )?;
+    output.flush()
+}
+
+fn handle(engine: &Mutex<Engine>, request: &Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["events"]) => ingest(engine, &request.body),
+        ("GET", ["events", id]) => match Uuid::parse_str(id) {
+            Ok(id) => match engine.lock().unwrap().get(&id) {
+                Some(info) => Response::ok(info.to_json(&id)),
+                None => Response::error(404, format!("no placement for {}", id)),
+            },
+            Err(error) => Response::error(400, format!("invalid id {}: {}", id, error)),
+        },
+        ("GET", ["average"]) => {
+            let average = engine.lock().unwrap().flee_average();
+            Response::ok(json!({
+                "flee_average": average.map(|point| point.coordinates.to_vec()),
+            }))
+        }
+        ("GET", ["nearest"]) => nearest(engine, &request.query),
+        (_, ["events"] | ["events", _] | ["average"] | ["nearest"]) => {
+            Response::error(405, format!("{} is not allowed here", request.method))
+        }
+        _ => Response::error(404, format!("no such endpoint: {}", request.path)),
+    }
+}
+
+fn ingest(engine: &Mutex<Engine>, body: &[u8]) -> Response {
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(error) => return Response::error(400, format!("invalid JSON: {}", error)),
+    };
+    let event = match Event::from_json(&value) {
+        Ok(event) => event,
+        Err(error) => return Response::error(400, error),
+    };
+    match engine.lock().unwrap().ingest(&event) {
+        Ok(info) => Response::ok(info.to_json(&event.id)),
+        Err(error) => Response::error(422, error.to_string()),
+    }
+}
+
+fn nearest(engine: &Mutex<Engine>, query: &HashMap<String, String>) -> Response {
+    let number = |name: &str| -> Result<Option<u64>, Response> {
+        query
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Response::error(400, format!("invalid {}: {}", name, value)))
+            })
+            .transpose()
+    };
+    let coordinate = |name: &str| -> Result<u32, Response> {
+        number(name)?
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| Response::error(400, format!("{} must be a 32-bit coordinate", name)))
+    };
+
+    let result = (|| {
+        let point = TorusPoint::new([coordinate("x")?, coordinate("y")?]);
+        let k = number("k")?.map_or(DEFAULT_NEIGHBOURS, |k| k as usize);
+        let engine = engine.lock().unwrap();
+        let neighbours = match query.get("of").map(String::as_str) {
+            None | Some("follow") => engine.nearest_follows(&point, k),
+            Some("flee") => engine.nearest_flees(&point, k),
+            Some(other) => {
+                return Err(Response::error(
+                    400,
+                    format!("of must be follow or flee, not {}", other),
+                ))
+            }
+        };
+        Ok(json!({
+            "neighbours": neighbours
+                .into_iter()
+                .map(|(id, distance)| json!({
+                    "id": id.to_string(),
+                    // the squared distance is at most 2^63 and so exact as a JSON integer
+                    "distance_squared": distance as u64,
+                }))
+                .collect::<Vec<_>>(),
+        }))
+    })();
+    result.map_or_else(|error| error, Response::ok)
+}
+
+fn serve_connection(
+    engine: &Mutex<Engine>,
+    stream: TcpStream,
+    timeout: Duration,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now() + timeout,
+    });
+    match read_request(&mut reader) {
+        Ok(request) => write_response(&mut &stream, &handle(engine, &request)),
+        Err(response) => {
+            write_response(&mut &stream, &response)?;
+            // closing with the rest of the request unread would reset the connection, and the
+            // client could lose the response; read on until it stops sending, or for one more
+            // timeout at most
+            stream.shutdown(Shutdown::Write)?;
+            reader.get_mut().deadline = Instant::now() + timeout;
+            let _ = io::copy(&mut reader.take(MAX_BODY as u64), &mut io::sink());
+            Ok(())
+        }
+    }
+}
+
+// One of the connections being served, given back when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(open: &Arc<AtomicUsize>, limit: usize) -> Option<Slot> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            (count < limit).then_some(count + 1)
+        })
+        .ok()
+        .map(|_| Slot(Arc::clone(open)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Answers each connection on a thread of its own, one request per connection, with at most
+// `max_connections` open at once.
+fn serve(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine>>,
+    timeout: Duration,
+    max_connections: usize,
+) {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("accept failed: {}", error);
+                continue;
+            }
+        };
+        let Some(slot) = Slot::take(&open, max_connections) else {
+            let busy = Response::error(503, "too many connections");
+            let _ = stream
+                .set_write_timeout(Some(timeout))
+                .and_then(|_| write_response(&mut stream, &busy));
+            continue;
+        };
+        let engine = Arc::clone(&engine);
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(error) = serve_connection(&engine, stream, timeout) {
+                eprintln!("connection failed: {}", error);
+            }
+        });
+    }
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let listener = match TcpListener::bind(options.bind) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("{}: {}", options.bind, error);
+            process::exit(1);
+        }
+    };
+    eprintln!("listening on http://{}", options.bind);
+    serve(
+        listener,
+        Arc::new(Mutex::new(Engine::new(options.capacity))),
+        TIMEOUT,
+        MAX_CONNECTIONS,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    // Starts a server on a free loopback port and returns its address.
+    fn start(capacity: usize) -> SocketAddr {
+        start_limited(capacity, MAX_CONNECTIONS)
+    }
+
+    fn start_limited(capacity: usize, max_connections: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let engine = Arc::new(Mutex::new(Engine::new(capacity)));
+        thread::spawn(move || {
+            serve(
+                listener,
+                engine,
+                Duration::from_millis(500),
+                max_connections,
+            )
+        });
+        address
+    }
+
+    fn request(address: SocketAddr, method: &str, target: &str, body: &str) -> (u16, Value) {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            body.len(),
+            body
+        );
+        send(address, raw.as_bytes())
+    }
+
+    // Sends `raw` as it is and reads the response.
+    fn send(address: SocketAddr, raw: &[u8]) -> (u16, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(raw).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(args(&["--bind", "[::1]:9000", "-c", "8"])),
+            Ok(Options {
+                bind: "[::1]:9000".parse().unwrap(),
+                capacity: 8,
+            })
+        );
+        assert!(parse_args(args(&["--bind", "0.0.0.0:8080"])).is_err());
+        assert!(parse_args(args(&["--capacity", "0"])).is_err());
+        assert!(parse_args(args(&["--port"])).is_err());
+    }
+
+    #[test]
+    fn test_post_and_get_placements() {
+        let address = start(4);
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let (status, first) = request(
+            address,
+            "POST",
+            "/events",
+            &json!({ "id": a.to_string() }).to_string(),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(first["id"], a.to_string());
+        assert_eq!(first["follow"], json!([1u32 << 31, 1u32 << 31]));
+        assert_eq!(first["weight"], 1);
+
+        let body = json!({ "id": b.to_string(), "timestamp": 5, "weight": 2 }).to_string();
+        let (status, second) = request(address, "POST", "/events", &body);
+        assert_eq!(status, 200);
+        assert_eq!(second["timestamp"], 5);
+
+        assert_eq!(
+            request(address, "GET", &format!("/events/{}", a), ""),
+            (200, first)
+        );
+        assert_eq!(
+            request(address, "GET", &format!("/events/{}", b), ""),
+            (200, second)
+        );
+        let (status, _) = request(address, "GET", &format!("/events/{}", Uuid::new_v4()), "");
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_average_and_nearest() {
+        let address = start(4);
+        assert_eq!(
+            request(address, "GET", "/average", ""),
+            (200, json!({ "flee_average": null }))
+        );
+
+        let id = Uuid::new_v4();
+        let (_, placed) = request(
+            address,
+            "POST",
+            "/events",
+            &json!({ "id": id.to_string() }).to_string(),
+        );
+        let (status, average) = request(address, "GET", "/average", "");
+        assert_eq!(status, 200);
+        assert_eq!(average["flee_average"], placed["flee"]);
+
+        let flee = &placed["flee"];
+        let target = format!("/nearest?x={}&y={}&k=3&of=flee", flee[0], flee[1]);
+        assert_eq!(
+            request(address, "GET", &target, ""),
+            (
+                200,
+                json!({ "neighbours": [{ "id": id.to_string(), "distance_squared": 0 }] })
+            )
+        );
+        let (status, follows) = request(address, "GET", "/nearest?x=0&y=0", "");
+        assert_eq!(status, 200);
+        assert_eq!(follows["neighbours"][0]["id"], id.to_string());
+    }
+
+    #[test]
+    fn test_rejects_bad_requests() {
+        let address = start(4);
+        assert_eq!(request(address, "POST", "/events", "{").0, 400);
+        assert_eq!(request(address, "POST", "/events", r#"{"id": "x"}"#).0, 400);
+        assert_eq!(request(address, "GET", "/events/not-a-uuid", "").0, 400);
+        assert_eq!(request(address, "GET", "/nearest?x=1", "").0, 400);
+        assert_eq!(
+            request(address, "GET", "/nearest?x=1&y=2&of=both", "").0,
+            400
+        );
+        assert_eq!(request(address, "DELETE", "/average", "").0, 405);
+        let (status, body) = request(address, "GET", "/metrics", "");
+        assert_eq!(status, 404);
+        assert!(body["error"].is_string());
+    }
+
+    #[test]
+    fn test_limits_slow_and_oversized_requests() {
+        let address = start(4);
+
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD));
+        assert_eq!(send(address, long_target.as_bytes()).0, 431);
+        let mut many_headers = b"GET /average HTTP/1.1\r\n".to_vec();
+        for _ in 0..MAX_HEAD / 16 {
+            many_headers.extend_from_slice(b"X-Padding: abc\r\n");
+        }
+        many_headers.extend_from_slice(b"\r\n");
+        assert_eq!(send(address, &many_headers).0, 431);
+
+        // headers that never end, and a body that never comes
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /average HTTP/1.1\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        let (status, body) = send(
+            address,
+            b"POST /events HTTP/1.1\r\nContent-Length: 10\r\n\r\n{",
+        );
+        assert_eq!(status, 408);
+        assert!(body["error"].is_string());
+
+        // and the server still answers afterwards
+        assert_eq!(request(address, "GET", "/average", "").0, 200);
+    }
+
+    #[test]
+    fn test_times_out_a_request_sent_a_byte_at_a_time() {
+        let address = start(4);
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(3)))
+            .unwrap();
+        stream.write_all(b"GET /average HTTP/1.1\r\n").unwrap();
+
+        // every byte comes well within the timeout, but the head never ends
+        let mut writer = stream.try_clone().unwrap();
+        let sending = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let still_sending = Arc::clone(&sending);
+        let trickle = thread::spawn(move || {
+            while still_sending.load(Ordering::Acquire) && writer.write_all(b"X").is_ok() {
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let start = Instant::now();
+        let mut response = Vec::new();
+        let mut buffer = [0; 256];
+        while !response.windows(2).any(|pair| pair == b"\r\n") {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0);
+            response.extend_from_slice(&buffer[..read]);
+        }
+        sending.store(false, Ordering::Release);
+        trickle.join().unwrap();
+
+        assert!(response.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_refuses_connections_over_the_limit() {
+        let address = start_limited(4, 1);
+        let idle = TcpStream::connect(address).unwrap();
+        // let the server take the idle connection's slot first
+        thread::sleep(Duration::from_millis(100));
+
+        let mut refused = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // once the idle connection times out and closes, its slot is free again
+        let mut response = [0; 12];
+        (&idle).read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"HTTP/1.1 408");
+        drop(idle);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(request(address, "GET", "/average", "").0, 200);
+    }
+}
@@ -0,0 +1,444 @@
+use inverse_pairs::{Engine, Event};
+use serde_json::{json, Value};
+use std::process;
+use uuid::Uuid;
+
+const USAGE: &str = "usage: inverse-pairs-socket [--capacity N] SOCKET
+
+Serves one in-process engine on the Unix socket SOCKET, speaking newline-delimited JSON: each line
+is one of {\"command\": \"ingest\", \"event\": {...}}, {\"command\": \"get\", \"id\": ...},
+{\"command\": \"stats\"} or {\"command\": \"snapshot\"}, and is answered by one line holding
+{\"ok\": ...} or {\"error\": ...}. A socket file left behind by a server that has stopped is
+replaced.
+
+A request line may hold at most 64 KiB and must arrive whole within 10 seconds of the previous
+answer; a connection that breaks either limit is answered with an error and closed, and one left
+idle that long is closed. At most 64 connections are served at once.";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    capacity: usize,
+    socket: String,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut capacity = 64;
+    let mut socket = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--capacity" => {
+                let value = args.next().ok_or("--capacity needs a value")?;
+                capacity = match value.parse() {
+                    Ok(capacity) if capacity > 0 => capacity,
+                    _ => return Err(format!("invalid capacity: {}", value)),
+                };
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown flag: {}", arg)),
+            _ if socket.is_some() => return Err("only one socket path is allowed".into()),
+            _ => socket = Some(arg),
+        }
+    }
+
+    Ok(Options {
+        capacity,
+        socket: socket.ok_or("a socket path is required")?,
+    })
+}
+
+// Answers one request line, as the line to send back.
+fn respond(engine: &mut Engine, line: &str) -> Value {
+    match execute(engine, line) {
+        Ok(result) => json!({ "ok": result }),
+        Err(message) => json!({ "error": message }),
+    }
+}
+
+fn execute(engine: &mut Engine, line: &str) -> Result<Value, String> {
+    let request: Value =
+        serde_json::from_str(line).map_err(|error| format!("invalid JSON: {}", error))?;
+    let field = |name: &str| {
+        request
+            .get(name)
+            .ok_or_else(|| format!("request has no \"{}\" field", name))
+    };
+
+    match field("command")?.as_str() {
+        Some("ingest") => {
+            let event = Event::from_json(field("event")?)?;
+            let info = engine.ingest(&event).map_err(|error| error.to_string())?;
+            Ok(info.to_json(&event.id))
+        }
+        Some("get") => {
+            let id = field("id")?
+                .as_str()
+                .and_then(|id| Uuid::parse_str(id).ok())
+                .ok_or("\"id\" must be a UUID string")?;
+            Ok(engine
+                .get(&id)
+                .map_or(Value::Null, |info| info.to_json(&id)))
+        }
+        Some("stats") => Ok(json!({
+            "capacity": engine.buffer().capacity(),
+            "window": engine.buffer().len(),
+            "placements": engine.map().len(),
+            "flee_average": engine.flee_average().map(|point| point.coordinates.to_vec()),
+        })),
+        Some("snapshot") => Ok(engine.to_snapshot()),
+        _ => Err(format!("unknown command: {}", request["command"])),
+    }
+}
+
+#[cfg(unix)]
+mod server {
+    use super::respond;
+    use inverse_pairs::Engine;
+    use serde_json::json;
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // a request line with its newline; requests are single commands, so anything larger is a
+    // mistake
+    pub const MAX_LINE: usize = 64 * 1024;
+    // for each request line and each answer, so a stalled or trickling client cannot hold a
+    // thread for long
+    pub const TIMEOUT: Duration = Duration::from_secs(10);
+    // each connection has a thread, so this bounds the threads too
+    pub const MAX_CONNECTIONS: usize = 64;
+
+    // Binds `path`, first removing a socket nobody is listening on any more.
+    pub fn bind(path: &Path) -> io::Result<UnixListener> {
+        match UnixListener::bind(path) {
+            Err(error) if error.kind() == ErrorKind::AddrInUse => match UnixStream::connect(path) {
+                Err(stale) if stale.kind() == ErrorKind::ConnectionRefused => {
+                    fs::remove_file(path)?;
+                    UnixListener::bind(path)
+                }
+                _ => Err(error),
+            },
+            result => result,
+        }
+    }
+
+    // A stream whose reads all have to finish by one deadline, however the bytes are spread
+    // out; a read after it fails with `TimedOut`.
+    struct DeadlineReader {
+        stream: UnixStream,
+        deadline: Instant,
+    }
+
+    impl Read for DeadlineReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "the request deadline passed",
+                ));
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            self.stream.read(buf)
+        }
+    }
+
+    // One of the connections being served, given back when dropped.
+    struct Slot(Arc<AtomicUsize>);
+
+    impl Slot {
+        fn take(open: &Arc<AtomicUsize>, limit: usize) -> Option<Slot> {
+            open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < limit).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(open)))
+        }
+    }
+
+    impl Drop for Slot {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    // Answers each connection on a thread of its own, request by request until it closes, with
+    // at most `max_connections` open at once.
+    pub fn serve(
+        listener: UnixListener,
+        engine: Arc<Mutex<Engine>>,
+        timeout: Duration,
+        max_connections: usize,
+    ) {
+        let open = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    eprintln!("accept failed: {}", error);
+                    continue;
+                }
+            };
+            let Some(slot) = Slot::take(&open, max_connections) else {
+                let busy = json!({ "error": "too many connections" });
+                let _ = stream
+                    .set_write_timeout(Some(timeout))
+                    .and_then(|_| writeln!(stream, "{}", busy));
+                continue;
+            };
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(error) = serve_connection(&engine, stream, timeout) {
+                    eprintln!("connection failed: {}", error);
+                }
+            });
+        }
+    }
+
+    fn serve_connection(
+        engine: &Mutex<Engine>,
+        stream: UnixStream,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        writer.set_write_timeout(Some(timeout))?;
+        let mut reader = BufReader::new(DeadlineReader {
+            stream,
+            deadline: Instant::now() + timeout,
+        });
+
+        loop {
+            reader.get_mut().deadline = Instant::now() + timeout;
+            let mut line = Vec::new();
+            let refusal = match reader
+                .by_ref()
+                .take(MAX_LINE as u64 + 1)
+                .read_until(b'\n', &mut line)
+            {
+                Ok(0) => return Ok(()),
+                Ok(_) if line.len() > MAX_LINE => "request line is too long",
+                Ok(_) => {
+                    let response = match String::from_utf8(line) {
+                        Ok(line) if line.trim().is_empty() => continue,
+                        Ok(line) => respond(&mut engine.lock().unwrap(), &line),
+                        Err(_) => json!({ "error": "request line is not UTF-8" }),
+                    };
+                    writeln!(writer, "{}", response)?;
+                    continue;
+                }
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    // a client that has sent nothing since its last answer is only idle
+                    if line.is_empty() {
+                        return Ok(());
+                    }
+                    "timed out waiting for the rest of the request"
+                }
+                Err(error) => return Err(error),
+            };
+
+            writeln!(writer, "{}", json!({ "error": refusal }))?;
+            // closing with the rest of the line unread would reset the connection, and the
+            // client could lose the answer; read on until it stops sending, or for one more
+            // timeout at most
+            writer.shutdown(Shutdown::Write)?;
+            reader.get_mut().deadline = Instant::now() + timeout;
+            let _ = io::copy(&mut reader.take(MAX_LINE as u64), &mut io::sink());
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn main() {
+    use std::sync::{Arc, Mutex};
+
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let listener = match server::bind(options.socket.as_ref()) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("{}: {}", options.socket, error);
+            process::exit(1);
+        }
+    };
+    eprintln!("listening on {}", options.socket);
+    server::serve(
+        listener,
+        Arc::new(Mutex::new(Engine::new(options.capacity))),
+        server::TIMEOUT,
+        server::MAX_CONNECTIONS,
+    );
+}
+
+#[cfg(not(unix))]
+fn main() {
+    let _ = (parse_args, respond);
+    eprintln!(
+        "Unix domain sockets are not available on this platform\n\n{}",
+        USAGE
+    );
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(args(&["-c", "8", "engine.sock"])),
+            Ok(Options {
+                capacity: 8,
+                socket: "engine.sock".to_string(),
+            })
+        );
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["a.sock", "b.sock"])).is_err());
+        assert!(parse_args(args(&["--capacity", "0", "a.sock"])).is_err());
+    }
+
+    #[test]
+    fn test_respond() {
+        let mut engine = Engine::new(2);
+        let id = Uuid::new_v4();
+        let ingest = json!({ "command": "ingest", "event": { "id": id.to_string() } });
+
+        let placed = respond(&mut engine, &ingest.to_string());
+        assert_eq!(placed["ok"], engine.get(&id).unwrap().to_json(&id));
+        assert_eq!(
+            respond(
+                &mut engine,
+                &json!({ "command": "get", "id": id.to_string() }).to_string()
+            ),
+            placed
+        );
+        assert_eq!(
+            respond(&mut engine, r#"{"command": "stats"}"#)["ok"]["window"],
+            1
+        );
+
+        for bad in [
+            "{",
+            r#"{"id": 1}"#,
+            r#"{"command": "flush"}"#,
+            r#"{"command": "get", "id": "x"}"#,
+            r#"{"command": "ingest", "event": {}}"#,
+        ] {
+            assert!(respond(&mut engine, bad)["error"].is_string(), "{}", bad);
+        }
+    }
+
+    #[cfg(unix)]
+    mod serving {
+        use super::*;
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::os::unix::net::UnixStream;
+        use std::path::PathBuf;
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        // Serves a fresh engine on a socket in a directory of its own, with a short timeout.
+        fn start(max_connections: usize) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!("inverse-pairs-{}", Uuid::new_v4()));
+            std::fs::create_dir(&dir).unwrap();
+            let path = dir.join("engine.sock");
+            let listener = server::bind(&path).unwrap();
+            let engine = Arc::new(Mutex::new(Engine::new(4)));
+            thread::spawn(move || {
+                server::serve(
+                    listener,
+                    engine,
+                    Duration::from_millis(500),
+                    max_connections,
+                )
+            });
+            path
+        }
+
+        #[test]
+        fn test_times_out_a_line_sent_a_byte_at_a_time() {
+            let path = start(4);
+            let stream = UnixStream::connect(&path).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(3)))
+                .unwrap();
+
+            // every byte comes well within the timeout, but the line never ends
+            let mut writer = stream.try_clone().unwrap();
+            let sending = Arc::new(std::sync::atomic::AtomicBool::new(true));
+            let still_sending = Arc::clone(&sending);
+            let trickle = thread::spawn(move || {
+                while still_sending.load(std::sync::atomic::Ordering::Acquire)
+                    && writer.write_all(b"{").is_ok()
+                {
+                    thread::sleep(Duration::from_millis(50));
+                }
+            });
+
+            let start = Instant::now();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            sending.store(false, std::sync::atomic::Ordering::Release);
+            trickle.join().unwrap();
+
+            let response: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(
+                response["error"],
+                "timed out waiting for the rest of the request"
+            );
+            assert!(start.elapsed() < Duration::from_secs(2));
+            std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+
+        #[test]
+        fn test_refuses_connections_over_the_limit() {
+            let path = start(1);
+            let idle = UnixStream::connect(&path).unwrap();
+            // let the server take the idle connection's slot first
+            thread::sleep(Duration::from_millis(100));
+
+            let mut response = String::new();
+            UnixStream::connect(&path)
+                .unwrap()
+                .read_to_string(&mut response)
+                .unwrap();
+            assert_eq!(response, "{\"error\":\"too many connections\"}\n");
+
+            // an idle connection is closed once it times out, which frees its slot
+            let mut rest = String::new();
+            (&idle).read_to_string(&mut rest).unwrap();
+            assert_eq!(rest, "");
+            thread::sleep(Duration::from_millis(100));
+            let mut stream = UnixStream::connect(&path).unwrap();
+            writeln!(stream, r#"{{"command": "stats"}}"#).unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Value>(&line).unwrap()["ok"]["window"],
+                0
+            );
+            std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::circular_mean_accumulator::CircularMeanAccumulator;
+use crate::error::Error;
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use crate::process_event::EventInfo;
+use crate::snapshot::SnapshotError;
+use crate::toroidal_rolling_flee_average::toroidal_rolling_flee_average;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// How the centroid new events are placed opposite to is kept up to date. `process_event` calls
+// `admit` before each push and `moved` when a repeated event moves its placement; the strategy
+// decides how much of the window or of the past counts.
+//
+// A call that returns an error must leave the strategy unchanged, so a rejected event changes
+// nothing. When the `admit` after a `moved` fails, `process_event` restores a copy of the
+// strategy taken before the move, which is why strategies are `Clone`.
+pub trait CentroidStrategy<const D: usize>: Clone {
+    // identifies the strategy in snapshots
+    const NAME: &'static str;
+
+    fn torus(&self) -> &Torus<D>;
+
+    // `latest_uuid` is about to be pushed onto the front of `event_buffer`, evicting its back id
+    // when full; both placements are in `event_map`.
+    fn admit(
+        &mut self,
+        event_map: &HashMap<Uuid, EventInfo<D>>,
+        event_buffer: &FixedCircularBuffer<Uuid>,
+        latest_uuid: &Uuid,
+    ) -> Result<(), Error>;
+
+    // A placement that occurs `occurrences` times in the window changed from `old` to `new`.
+    fn moved(
+        &mut self,
+        old: &EventInfo<D>,
+        new: &EventInfo<D>,
+        occurrences: usize,
+    ) -> Result<(), Error>;
+
+    fn centroid(&self) -> Option<TorusPoint<D>>;
+
+    fn to_snapshot(&self) -> Value;
+
+    fn from_snapshot(torus: Torus<D>, snapshot: &Value) -> Result<Self, SnapshotError>
+    where
+        Self: Sized;
+}
+
+// The placements in the window once `latest_uuid` is pushed onto `event_buffer`, newest first.
+pub(crate) fn window_after_push<'a, const D: usize>(
+    event_map: &'a HashMap<Uuid, EventInfo<D>>,
+    event_buffer: &FixedCircularBuffer<Uuid>,
+    latest_uuid: &Uuid,
+) -> Result<Vec<&'a EventInfo<D>>, Error> {
+    if event_buffer.capacity() == 0 {
+        return Err(Error::ZeroCapacity);
+    }
+    let kept = event_buffer.len().min(event_buffer.capacity() - 1);
+    std::iter::once(latest_uuid)
+        .chain(event_buffer.into_iter().take(kept))
+        .map(|id| event_map.get(id).ok_or(Error::MissingId(*id)))
+        .collect()
+}
+
+// Reads the `centroid` field that strategies recomputing from the window save.
+pub(crate) fn centroid_from_snapshot<const D: usize>(
+    snapshot: &Value,
+) -> Result<Option<TorusPoint<D>>, SnapshotError> {
+    let invalid = || SnapshotError::Invalid("flee_sums.centroid".to_string());
+    match &snapshot["centroid"] {
+        Value::Null => Ok(None),
+        Value::Array(coordinates) => {
+            let coordinates = coordinates
+                .iter()
+                .map(|value| {
+                    value
+                        .as_u64()
+                        .and_then(|value| u32::try_from(value).ok())
+                        .ok_or_else(invalid)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let coordinates: [u32; D] = coordinates.try_into().map_err(|_| invalid())?;
+            Ok(Some(TorusPoint::new(coordinates)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// The windowed circular mean: every flee point in the buffer counts by its weight, and nothing
+// outside it counts at all.
+impl<const D: usize> CentroidStrategy<D> for CircularMeanAccumulator<D> {
+    const NAME: &'static str = "windowed";
+
+    fn torus(&self) -> &Torus<D> {
+        CircularMeanAccumulator::torus(self)
+    }
+
+    fn admit(
+        &mut self,
+        event_map: &HashMap<Uuid, EventInfo<D>>,
+        event_buffer: &FixedCircularBuffer<Uuid>,
+        latest_uuid: &Uuid,
+    ) -> Result<(), Error> {
+        toroidal_rolling_flee_average(event_map, event_buffer, self, latest_uuid).map(|_| ())
+    }
+
+    fn moved(
+        &mut self,
+        old: &EventInfo<D>,
+        new: &EventInfo<D>,
+        occurrences: usize,
+    ) -> Result<(), Error> {
+        let mut sums = *self;
+        for _ in 0..occurrences {
+            sums.remove_weighted(&old.flee, old.weight)?;
+            sums.add_weighted(&new.flee, new.weight)?;
+        }
+        *self = sums;
+        Ok(())
+    }
+
+    fn centroid(&self) -> Option<TorusPoint<D>> {
+        self.mean()
+    }
+
+    // i128 does not fit a JSON number, so the sums are decimal strings
+    fn to_snapshot(&self) -> Value {
+        let (sum_cos, sum_sin) = self.sums();
+        json!({
+            "count": self.count,
+            "cos": sum_cos.iter().map(i128::to_string).collect::<Vec<_>>(),
+            "sin": sum_sin.iter().map(i128::to_string).collect::<Vec<_>>(),
+        })
+    }
+
+    fn from_snapshot(torus: Torus<D>, snapshot: &Value) -> Result<Self, SnapshotError> {
+        let invalid = || SnapshotError::Invalid("flee_sums".to_string());
+        let sums = |field: &str| -> Result<[i128; D], SnapshotError> {
+            let sums = snapshot[field]
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|sum| {
+                    sum.as_str()
+                        .and_then(|sum| sum.parse().ok())
+                        .ok_or_else(invalid)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            sums.try_into().map_err(|_| invalid())
+        };
+        let count = snapshot["count"].as_u64().ok_or_else(invalid)? as usize;
+        Ok(CircularMeanAccumulator::from_sums(
+            torus,
+            sums("cos")?,
+            sums("sin")?,
+            count,
+        ))
+    }
+}
@@ -0,0 +1,308 @@
+use crate::error::Error;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use std::f64::consts::TAU;
+
+const UNIT: f64 = (1u64 << 52) as f64; // fixed point scale for one unit vector component
+
+// Running sums of unit vectors per axis, so each coordinate is treated as an angle on its own
+// circle and the mean does not break across the wrap seam. Components are summed as fixed point
+// integers in i128, so removing a point exactly undoes adding it and evictions cannot drift the
+// sums; the arithmetic is still checked so a broken invariant surfaces as an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircularMeanAccumulator<const D: usize = 2> {
+    sum_cos: [i128; D],
+    sum_sin: [i128; D],
+    pub count: usize,
+    torus: Torus<D>,
+}
+
+// One full turn of the circle is one full lap of the axis.
+fn to_unit_vector(coordinate: u32, axis_size: u64) -> (i128, i128) {
+    let angle = (coordinate as u64 % axis_size) as f64 / axis_size as f64 * TAU;
+    (
+        (angle.cos() * UNIT).round() as i128,
+        (angle.sin() * UNIT).round() as i128,
+    )
+}
+
+// The coordinate on an axis of `axis_size` points at the angle of the summed vector.
+pub(crate) fn from_angle(sum_sin: f64, sum_cos: f64, axis_size: u64) -> u32 {
+    let turns = (sum_sin.atan2(sum_cos) / TAU).rem_euclid(1.0);
+    ((turns * axis_size as f64).round() as u64 % axis_size) as u32
+}
+
+impl CircularMeanAccumulator {
+    pub fn new() -> Self {
+        Self::with_torus(Torus::FULL)
+    }
+}
+
+impl<const D: usize> CircularMeanAccumulator<D> {
+    pub fn with_torus(torus: Torus<D>) -> Self {
+        CircularMeanAccumulator {
+            sum_cos: [0; D],
+            sum_sin: [0; D],
+            count: 0,
+            torus,
+        }
+    }
+
+    pub fn torus(&self) -> &Torus<D> {
+        &self.torus
+    }
+
+    // The raw fixed point sums, for snapshots.
+    pub(crate) fn sums(&self) -> (&[i128; D], &[i128; D]) {
+        (&self.sum_cos, &self.sum_sin)
+    }
+
+    pub(crate) fn from_sums(
+        torus: Torus<D>,
+        sum_cos: [i128; D],
+        sum_sin: [i128; D],
+        count: usize,
+    ) -> Self {
+        CircularMeanAccumulator {
+            sum_cos,
+            sum_sin,
+            count,
+            torus,
+        }
+    }
+
+    // Applies `step` to every running sum and the new count, committing only if all of them fit.
+    fn update(
+        &mut self,
+        point: &TorusPoint<D>,
+        weight: u64,
+        count: usize,
+        step: fn(i128, i128) -> Option<i128>,
+    ) -> Result<(), Error> {
+        let mut sum_cos = self.sum_cos;
+        let mut sum_sin = self.sum_sin;
+        for (axis, size) in self.torus.sizes().iter().enumerate() {
+            let (cos, sin) = to_unit_vector(point[axis], *size);
+            sum_cos[axis] = step(sum_cos[axis], cos * weight as i128).ok_or(Error::Overflow)?;
+            sum_sin[axis] = step(sum_sin[axis], sin * weight as i128).ok_or(Error::Overflow)?;
+        }
+        self.sum_cos = sum_cos;
+        self.sum_sin = sum_sin;
+        self.count = count;
+        Ok(())
+    }
+
+    pub fn add(&mut self, point: &TorusPoint<D>) -> Result<(), Error> {
+        self.add_weighted(point, 1)
+    }
+
+    pub fn remove(&mut self, point: &TorusPoint<D>) -> Result<(), Error> {
+        self.remove_weighted(point, 1)
+    }
+
+    // A point of weight w pulls the mean like w copies of it; removing must use the same weight.
+    pub fn add_weighted(&mut self, point: &TorusPoint<D>, weight: u32) -> Result<(), Error> {
+        self.add_wide(point, weight as u64)
+    }
+
+    pub fn remove_weighted(&mut self, point: &TorusPoint<D>, weight: u32) -> Result<(), Error> {
+        let count = self.count.checked_sub(1).ok_or(Error::EmptyWindow)?;
+        self.update(point, weight as u64, count, i128::checked_sub)
+    }
+
+    // As `add_weighted`, for weights that are products of an event weight and something else.
+    // A unit vector times a u64 weight still fits an i128.
+    pub(crate) fn add_wide(&mut self, point: &TorusPoint<D>, weight: u64) -> Result<(), Error> {
+        let count = self.count.checked_add(1).ok_or(Error::Overflow)?;
+        self.update(point, weight, count, i128::checked_add)
+    }
+
+    // Folds in another accumulator's points, as if each had been added here. Both must measure
+    // angles on the same torus.
+    pub fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        let mut merged = *self;
+        merged.count = self.count.checked_add(other.count).ok_or(Error::Overflow)?;
+        for axis in 0..D {
+            merged.sum_cos[axis] = self.sum_cos[axis]
+                .checked_add(other.sum_cos[axis])
+                .ok_or(Error::Overflow)?;
+            merged.sum_sin[axis] = self.sum_sin[axis]
+                .checked_add(other.sum_sin[axis])
+                .ok_or(Error::Overflow)?;
+        }
+        *self = merged;
+        Ok(())
+    }
+
+    pub fn mean(&self) -> Option<TorusPoint<D>> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(TorusPoint::new(std::array::from_fn(|axis| {
+            from_angle(
+                self.sum_sin[axis] as f64,
+                self.sum_cos[axis] as f64,
+                self.torus.sizes()[axis],
+            )
+        })))
+    }
+}
+
+impl<const D: usize> Default for CircularMeanAccumulator<D> {
+    fn default() -> Self {
+        Self::with_torus(Torus::FULL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circular_mean_empty() {
+        assert_eq!(CircularMeanAccumulator::new().mean(), None);
+    }
+
+    #[test]
+    fn test_circular_mean_merge_matches_adding_everything() {
+        let torus = Torus::new([1000, 360]).unwrap();
+        let points = [[990, 10], [5, 350], [20, 0], [500, 180]].map(TorusPoint::new);
+        let (mut left, mut right, mut all) = (
+            CircularMeanAccumulator::with_torus(torus),
+            CircularMeanAccumulator::with_torus(torus),
+            CircularMeanAccumulator::with_torus(torus),
+        );
+        for (i, point) in points.iter().enumerate() {
+            let half = if i % 2 == 0 { &mut left } else { &mut right };
+            half.add_weighted(point, i as u32 + 1).unwrap();
+            all.add_weighted(point, i as u32 + 1).unwrap();
+        }
+
+        left.merge(&right).unwrap();
+        assert_eq!(left, all);
+    }
+
+    #[test]
+    fn test_circular_mean_single_point() {
+        let mut accumulator = CircularMeanAccumulator::new();
+        accumulator
+            .add(&TorusPoint::new([12345, u32::MAX / 3]))
+            .unwrap();
+        assert_eq!(
+            accumulator.mean(),
+            Some(TorusPoint::new([12345, u32::MAX / 3]))
+        );
+    }
+
+    #[test]
+    fn test_circular_mean_across_seam() {
+        let mut accumulator = CircularMeanAccumulator::new();
+        accumulator
+            .add(&TorusPoint::new([1, u32::MAX - 9]))
+            .unwrap();
+        accumulator.add(&TorusPoint::new([u32::MAX, 12])).unwrap();
+        assert_eq!(accumulator.mean(), Some(TorusPoint::new([0, 1])));
+    }
+
+    #[test]
+    fn test_circular_mean_quarters() {
+        let eighth = 1u32 << 29;
+        let mut accumulator = CircularMeanAccumulator::new();
+        accumulator.add(&TorusPoint::new([eighth * 2, 0])).unwrap();
+        accumulator
+            .add(&TorusPoint::new([eighth * 4, eighth * 2]))
+            .unwrap();
+        assert_eq!(
+            accumulator.mean(),
+            Some(TorusPoint::new([eighth * 3, eighth]))
+        );
+    }
+
+    #[test]
+    fn test_circular_mean_remove() {
+        let mut accumulator = CircularMeanAccumulator::new();
+        accumulator.add(&TorusPoint::new([100, 200])).unwrap();
+        accumulator
+            .add(&TorusPoint::new([u32::MAX / 2, u32::MAX / 2]))
+            .unwrap();
+        accumulator
+            .remove(&TorusPoint::new([u32::MAX / 2, u32::MAX / 2]))
+            .unwrap();
+        assert_eq!(accumulator.count, 1);
+        assert_eq!(accumulator.mean(), Some(TorusPoint::new([100, 200])));
+
+        accumulator.remove(&TorusPoint::new([100, 200])).unwrap();
+        assert_eq!(accumulator.mean(), None);
+
+        // removing from an empty accumulator leaves it untouched
+        assert_eq!(
+            accumulator.remove(&TorusPoint::new([100, 200])),
+            Err(Error::EmptyWindow)
+        );
+        assert_eq!(accumulator.count, 0);
+    }
+
+    #[test]
+    fn test_circular_mean_weighted_matches_repeated_points() {
+        let torus = Torus::new([360, 360]).unwrap();
+        let mut weighted = CircularMeanAccumulator::with_torus(torus);
+        let mut repeated = CircularMeanAccumulator::with_torus(torus);
+
+        weighted.add_weighted(&TorusPoint::new([0, 0]), 3).unwrap();
+        weighted.add_weighted(&TorusPoint::new([90, 0]), 1).unwrap();
+        for _ in 0..3 {
+            repeated.add(&TorusPoint::new([0, 0])).unwrap();
+        }
+        repeated.add(&TorusPoint::new([90, 0])).unwrap();
+
+        assert_eq!(weighted.sums(), repeated.sums());
+        assert_eq!(weighted.mean(), Some(TorusPoint::new([18, 0])));
+
+        weighted
+            .remove_weighted(&TorusPoint::new([0, 0]), 3)
+            .unwrap();
+        assert_eq!(weighted.count, 1);
+        assert_eq!(weighted.mean(), Some(TorusPoint::new([90, 0])));
+    }
+
+    #[test]
+    fn test_circular_mean_survives_many_evictions_without_drift() {
+        let mut accumulator = CircularMeanAccumulator::new();
+        let mut fresh = CircularMeanAccumulator::new();
+        fresh
+            .add(&TorusPoint::new([u32::MAX - 7, u32::MAX / 3]))
+            .unwrap();
+        fresh.add(&TorusPoint::new([u32::MAX, 5])).unwrap();
+
+        for i in 0..10_000u32 {
+            let point = TorusPoint::new([u32::MAX - i, i.wrapping_mul(2_654_435_761)]);
+            accumulator.add(&point).unwrap();
+            accumulator.remove(&point).unwrap();
+        }
+        accumulator
+            .add(&TorusPoint::new([u32::MAX - 7, u32::MAX / 3]))
+            .unwrap();
+        accumulator.add(&TorusPoint::new([u32::MAX, 5])).unwrap();
+
+        assert_eq!(accumulator, fresh);
+    }
+
+    #[test]
+    fn test_circular_mean_on_small_torus() {
+        let mut accumulator = CircularMeanAccumulator::with_torus(Torus::new([1024, 10]).unwrap());
+        accumulator.add(&TorusPoint::new([1020, 9])).unwrap();
+        accumulator.add(&TorusPoint::new([6, 3])).unwrap();
+        assert_eq!(accumulator.mean(), Some(TorusPoint::new([1, 1])));
+    }
+
+    #[test]
+    fn test_circular_mean_in_three_dimensions() {
+        let torus = Torus::new([1024, 1 << 32, 6]).unwrap();
+        let mut accumulator = CircularMeanAccumulator::with_torus(torus);
+        accumulator
+            .add(&TorusPoint::new([1020, u32::MAX, 5]))
+            .unwrap();
+        accumulator.add(&TorusPoint::new([6, 3, 1])).unwrap();
+        assert_eq!(accumulator.mean(), Some(TorusPoint::new([1, 1, 0])));
+    }
+}
@@ -0,0 +1,552 @@
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use uuid::Uuid;
+
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+
+// spare cells for pushes in flight, beyond the ones holding the window
+const DEFAULT_PRODUCERS: usize = 64;
+
+// A window of the most recent ids shared between threads, with the eviction semantics of
+// `FixedCircularBuffer`, but every operation takes `&self` and none of them waits on another
+// thread: pushes and snapshots are lock-free.
+//
+// A push first writes its id into a cell no other thread is using, then publishes it with a
+// single compare-and-swap of `head`, which packs the newest position with the cell holding its
+// id. The pushes that win those swaps, one position after another, are the total order. Each
+// position's entry in the ring is filled in by whichever thread gets to it first, before `head`
+// moves past it, so a producer descheduled anywhere in a push only ever delays its own push; the
+// others finish its bookkeeping for it or go around it. The push that takes position p evicts
+// position p - capacity and hands its cell back.
+//
+// A snapshot reads the window `head` points at and then checks `head` again; it retries only
+// if a push completed in between, so some thread always makes progress, though a reader racing
+// a steady stream of pushes may retry for as long as they keep coming. Pushes never wait for a
+// free cell while no more than `producers` of them are in flight at once.
+pub struct ConcurrentCircularBuffer {
+    capacity: usize,
+    cells: Box<[Cell]>,
+    // the entry of position p is at p % ring.len(), a power of two no smaller than the capacity
+    ring: Box<[AtomicU64]>,
+    // the newest position with its cell, or EMPTY before the first push
+    head: AtomicU64,
+    // where the next search for a free cell starts
+    next_cell: AtomicUsize,
+    // how many pushes have completed
+    published: AtomicU64,
+    // bits of a packed word that hold the cell index; the rest hold the position
+    cell_bits: u32,
+}
+
+struct Cell {
+    // 1 while a push owns the cell, 0 when it is free
+    owned: AtomicU64,
+    high: AtomicU64,
+    low: AtomicU64,
+}
+
+impl ConcurrentCircularBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_producers(capacity, DEFAULT_PRODUCERS)
+    }
+
+    // A buffer with a spare cell for each of `producers` pushes running at once. A push beyond
+    // that many waits until one of them finishes.
+    pub fn with_producers(capacity: usize, producers: usize) -> Self {
+        let cells = if capacity == 0 {
+            0
+        } else {
+            capacity + producers.max(1)
+        };
+        // one more index than there are cells stands for no cell at all
+        let cell_bits = usize::BITS - cells.leading_zeros();
+        let empty = (u64::MAX << cell_bits) | cells as u64;
+        ConcurrentCircularBuffer {
+            capacity,
+            cells: (0..cells)
+                .map(|_| Cell {
+                    owned: AtomicU64::new(0),
+                    high: AtomicU64::new(0),
+                    low: AtomicU64::new(0),
+                })
+                .collect(),
+            ring: (0..capacity.next_power_of_two())
+                .map(|_| AtomicU64::new(empty))
+                .collect(),
+            head: AtomicU64::new(empty),
+            next_cell: AtomicUsize::new(0),
+            published: AtomicU64::new(0),
+            cell_bits,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // How many pushes have completed.
+    pub fn pushes(&self) -> u64 {
+        self.published.load(Ordering::Acquire)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pushes().min(self.capacity as u64) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pushes() == 0
+    }
+
+    // Returns the id evicted to make room, which is `id` itself when the capacity is zero. Once
+    // this returns, every snapshot includes `id` until it is evicted.
+    pub fn push_front(&self, id: Uuid) -> Option<Uuid> {
+        if self.capacity == 0 {
+            return Some(id);
+        }
+        let cell = self.claim_cell();
+        // a reader that sees these stores also sees the cell's previous position evicted
+        fence(Ordering::Release);
+        let bits = id.as_u128();
+        self.cells[cell]
+            .high
+            .store((bits >> 64) as u64, Ordering::Relaxed);
+        self.cells[cell].low.store(bits as u64, Ordering::Relaxed);
+        self.publish(cell)
+    }
+
+    // Finds a free cell and takes it.
+    fn claim_cell(&self) -> usize {
+        let mut cell = self.next_cell.fetch_add(1, Ordering::Relaxed) % self.cells.len();
+        let mut tried = 0;
+        loop {
+            if self.cells[cell]
+                .owned
+                .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return cell;
+            }
+            cell = (cell + 1) % self.cells.len();
+            tried += 1;
+            // every cell is in the window or held by another push in flight
+            if tried % self.cells.len() == 0 {
+                backoff();
+            }
+        }
+    }
+
+    // Makes `cell` the newest position and returns the id of the position that leaves the window.
+    fn publish(&self, cell: usize) -> Option<Uuid> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let (position, _) = self.unpack(head);
+            if !self.is_empty_word(head) {
+                self.fill_entry(head);
+            }
+
+            let next = position.wrapping_add(1) & self.position_mask();
+            let leaving = next.wrapping_sub(self.capacity as u64) & self.position_mask();
+            let entry = self.entry(leaving).load(Ordering::Acquire);
+            let evicted = if self.is_empty_word(entry) {
+                None
+            } else {
+                match self.unpack(entry) {
+                    (entry_position, cell) if entry_position == leaving => Some(cell),
+                    // `head` has moved on since it was read
+                    _ => continue,
+                }
+            };
+
+            let claimed = self.pack(next, cell);
+            if self
+                .head
+                .compare_exchange(head, claimed, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                continue;
+            }
+            self.fill_entry(claimed);
+            self.published.fetch_add(1, Ordering::Release);
+
+            // only the push that evicts a position hands its cell back, so nobody else can
+            // reuse it before its id is read
+            return evicted.map(|evicted| {
+                let id = self.cells[evicted].read();
+                self.cells[evicted].owned.store(0, Ordering::Release);
+                id
+            });
+        }
+    }
+
+    // Records the position `head` packs in its ring entry, unless a newer one is there already.
+    fn fill_entry(&self, head: u64) {
+        let (position, _) = self.unpack(head);
+        let entry = self.entry(position);
+        let current = entry.load(Ordering::Acquire);
+        if current == head {
+            return;
+        }
+        if !self.is_empty_word(current) {
+            let (current_position, _) = self.unpack(current);
+            let age = position.wrapping_sub(current_position) & self.position_mask();
+            if age == 0 || age > self.position_mask() / 2 {
+                return;
+            }
+        }
+        // losing the race means another thread recorded this position or a newer one
+        let _ = entry.compare_exchange(current, head, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    // The window as it was at one instant, newest first, for the rolling average and anything
+    // else that works on a `FixedCircularBuffer`.
+    pub fn snapshot(&self) -> FixedCircularBuffer<Uuid> {
+        'retry: loop {
+            let head = self.head.load(Ordering::Acquire);
+            let mut ids = Vec::with_capacity(self.capacity);
+            if !self.is_empty_word(head) {
+                let (newest, _) = self.unpack(head);
+                for age in 0..self.capacity as u64 {
+                    let position = newest.wrapping_sub(age) & self.position_mask();
+                    let entry = if age == 0 {
+                        head
+                    } else {
+                        self.entry(position).load(Ordering::Acquire)
+                    };
+                    if self.is_empty_word(entry) {
+                        // fewer pushes than the capacity so far
+                        break;
+                    }
+                    match self.unpack(entry) {
+                        (entry_position, cell) if entry_position == position => {
+                            ids.push(self.cells[cell].read())
+                        }
+                        _ => {
+                            backoff();
+                            continue 'retry;
+                        }
+                    }
+                }
+            }
+
+            // a cell is handed back only once a later push evicts its position, so if no push
+            // completed meanwhile, every id read is the one its position was published with
+            fence(Ordering::Acquire);
+            if self.head.load(Ordering::Relaxed) != head {
+                backoff();
+                continue;
+            }
+
+            let mut window = FixedCircularBuffer::new(self.capacity);
+            for id in ids.into_iter().rev() {
+                window.push_front(id);
+            }
+            return window;
+        }
+    }
+
+    fn entry(&self, position: u64) -> &AtomicU64 {
+        &self.ring[(position % self.ring.len() as u64) as usize]
+    }
+
+    fn position_mask(&self) -> u64 {
+        u64::MAX >> self.cell_bits
+    }
+
+    fn pack(&self, position: u64, cell: usize) -> u64 {
+        position << self.cell_bits | cell as u64
+    }
+
+    fn unpack(&self, word: u64) -> (u64, usize) {
+        (
+            word >> self.cell_bits,
+            (word & !(u64::MAX << self.cell_bits)) as usize,
+        )
+    }
+
+    fn is_empty_word(&self, word: u64) -> bool {
+        self.unpack(word).1 == self.cells.len()
+    }
+}
+
+impl Cell {
+    fn read(&self) -> Uuid {
+        let high = self.high.load(Ordering::Relaxed) as u128;
+        let low = self.low.load(Ordering::Relaxed) as u128;
+        Uuid::from_u128(high << 64 | low)
+    }
+}
+
+#[cfg(loom)]
+fn backoff() {
+    loom::thread::yield_now();
+}
+
+#[cfg(not(loom))]
+fn backoff() {
+    std::thread::yield_now();
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    const PRODUCERS: u128 = 4;
+    const PUSHES: u128 = 5_000;
+
+    // producer in the high bits and its push count in the low ones
+    fn id(producer: u128, count: u128) -> Uuid {
+        Uuid::from_u128(producer << 64 | count)
+    }
+
+    fn producer_and_count(id: &Uuid) -> (u128, u128) {
+        (id.as_u128() >> 64, id.as_u128() as u64 as u128)
+    }
+
+    fn contents(window: &FixedCircularBuffer<Uuid>) -> Vec<Uuid> {
+        window.iter().copied().collect()
+    }
+
+    // Pushes from every producer at once and returns what each push evicted.
+    fn push_concurrently(buffer: &Arc<ConcurrentCircularBuffer>) -> Vec<(Uuid, Option<Uuid>)> {
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let buffer = Arc::clone(buffer);
+                thread::spawn(move || {
+                    (0..PUSHES)
+                        .map(|count| {
+                            let id = id(producer, count);
+                            (id, buffer.push_front(id))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        producers
+            .into_iter()
+            .flat_map(|producer| producer.join().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_single_thread_matches_fixed_circular_buffer() {
+        for capacity in [0, 1, 3] {
+            let concurrent = ConcurrentCircularBuffer::new(capacity);
+            let mut fixed = FixedCircularBuffer::new(capacity);
+            for count in 0..10 {
+                assert_eq!(
+                    concurrent.push_front(id(0, count)),
+                    fixed.push_front(id(0, count))
+                );
+                assert_eq!(contents(&concurrent.snapshot()), contents(&fixed));
+                assert_eq!(concurrent.len(), fixed.len());
+            }
+            assert_eq!(concurrent.snapshot().capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_pushes_evict_in_one_total_order() {
+        // with room for one id, each push evicts the push just before it in the total order
+        let buffer = Arc::new(ConcurrentCircularBuffer::new(1));
+        let pushes = push_concurrently(&buffer);
+
+        let mut next_of = std::collections::HashMap::new();
+        let mut first = None;
+        for (pushed, evicted) in &pushes {
+            match evicted {
+                Some(evicted) => assert!(next_of.insert(*evicted, *pushed).is_none()),
+                None => assert!(first.replace(*pushed).is_none()),
+            }
+        }
+
+        // walking the chain visits every push once, each producer's in the order it made them
+        let mut last_count = vec![None; PRODUCERS as usize];
+        let mut current = first.unwrap();
+        let mut visited = 1;
+        loop {
+            let (producer, count) = producer_and_count(&current);
+            assert_eq!(
+                last_count[producer as usize].map_or(0, |last| last + 1),
+                count
+            );
+            last_count[producer as usize] = Some(count);
+            match next_of.get(&current) {
+                Some(next) => (current, visited) = (*next, visited + 1),
+                None => break,
+            }
+        }
+        assert_eq!(visited, PRODUCERS * PUSHES);
+        assert_eq!(contents(&buffer.snapshot()), vec![current]);
+    }
+
+    #[test]
+    fn test_a_stalled_push_does_not_hold_up_the_others() {
+        // a push descheduled after taking its cell, as far as the other threads can tell; the
+        // second spare cell is for the pushes that go on without it
+        let buffer = Arc::new(ConcurrentCircularBuffer::with_producers(2, 2));
+        let stalled = buffer.claim_cell();
+
+        let others = {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || {
+                let evicted: Vec<_> = (0..5)
+                    .map(|count| buffer.push_front(id(1, count)))
+                    .collect();
+                (evicted, contents(&buffer.snapshot()))
+            })
+        };
+        let (evicted, window) = others.join().unwrap();
+        assert_eq!(
+            evicted,
+            vec![None, None, Some(id(1, 0)), Some(id(1, 1)), Some(id(1, 2))]
+        );
+        assert_eq!(window, vec![id(1, 4), id(1, 3)]);
+
+        // and once it runs again it lands after them
+        let bits = id(0, 0).as_u128();
+        buffer.cells[stalled]
+            .high
+            .store((bits >> 64) as u64, Ordering::Relaxed);
+        buffer.cells[stalled]
+            .low
+            .store(bits as u64, Ordering::Relaxed);
+        assert_eq!(buffer.publish(stalled), Some(id(1, 3)));
+        assert_eq!(contents(&buffer.snapshot()), vec![id(0, 0), id(1, 4)]);
+        assert_eq!(buffer.pushes(), 6);
+    }
+
+    #[test]
+    fn test_snapshots_are_consistent_windows_under_contention() {
+        let capacity = 8;
+        let buffer = Arc::new(ConcurrentCircularBuffer::new(capacity));
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || {
+                    let mut newest_seen = vec![None; PRODUCERS as usize];
+                    while buffer.pushes() < (PRODUCERS * PUSHES) as u64 {
+                        let window = contents(&buffer.snapshot());
+                        assert!(window.len() <= capacity);
+                        assert_eq!(window.iter().collect::<HashSet<_>>().len(), window.len());
+
+                        // a window is a run of consecutive pushes, so each producer's ids in it
+                        // are consecutive too, newest first, and never older than before
+                        let mut previous: Vec<Option<u128>> = vec![None; PRODUCERS as usize];
+                        for id in &window {
+                            let (producer, count) = producer_and_count(id);
+                            let producer = producer as usize;
+                            if let Some(previous) = previous[producer] {
+                                assert_eq!(count + 1, previous);
+                            } else {
+                                assert!(newest_seen[producer].is_none_or(|seen| seen <= count));
+                                newest_seen[producer] = Some(count);
+                            }
+                            previous[producer] = Some(count);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let pushes = push_concurrently(&buffer);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        // every id was evicted exactly once or is still in the window
+        let window = contents(&buffer.snapshot());
+        assert_eq!(window.len(), capacity);
+        let mut remaining: HashSet<Uuid> = pushes.iter().map(|(pushed, _)| *pushed).collect();
+        for evicted in pushes.iter().filter_map(|(_, evicted)| *evicted) {
+            assert!(remaining.remove(&evicted));
+        }
+        assert_eq!(remaining, window.into_iter().collect());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    #[test]
+    fn loom_pushes_and_snapshot_are_linearizable() {
+        loom::model(|| {
+            let buffer = Arc::new(ConcurrentCircularBuffer::new(2));
+            let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+            let producers: Vec<_> = [a, b]
+                .into_iter()
+                .map(|id| {
+                    let buffer = Arc::clone(&buffer);
+                    thread::spawn(move || buffer.push_front(id))
+                })
+                .collect();
+
+            let seen: Vec<Uuid> = buffer.snapshot().iter().copied().collect();
+            for producer in producers {
+                assert_eq!(producer.join().unwrap(), None);
+            }
+
+            // whatever the reader saw is the window after some prefix of the final order
+            let order: Vec<Uuid> = buffer.snapshot().iter().rev().copied().collect();
+            assert_eq!(order.len(), 2);
+            let mut prefix: Vec<Uuid> = order[..seen.len()].to_vec();
+            prefix.reverse();
+            assert_eq!(seen, prefix);
+        });
+    }
+
+    #[test]
+    fn loom_snapshot_never_sees_a_reused_cell() {
+        loom::model(|| {
+            // two cells, so the third push reuses the first push's cell
+            let buffer = Arc::new(ConcurrentCircularBuffer::with_producers(1, 1));
+            let ids = [1, 2, 3].map(Uuid::from_u128);
+            buffer.push_front(ids[0]);
+            let producer = {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || {
+                    assert_eq!(buffer.push_front(ids[1]), Some(ids[0]));
+                    assert_eq!(buffer.push_front(ids[2]), Some(ids[1]));
+                })
+            };
+
+            let seen: Vec<Uuid> = buffer.snapshot().iter().copied().collect();
+            assert_eq!(seen.len(), 1);
+            assert!(ids.contains(&seen[0]));
+            producer.join().unwrap();
+            assert_eq!(
+                buffer.snapshot().iter().copied().collect::<Vec<_>>(),
+                vec![ids[2]]
+            );
+        });
+    }
+
+    #[test]
+    fn loom_push_over_a_full_ring_evicts_the_oldest() {
+        loom::model(|| {
+            let buffer = Arc::new(ConcurrentCircularBuffer::new(1));
+            let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+            let other = {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || buffer.push_front(b))
+            };
+            let evicted_a = buffer.push_front(a);
+            let evicted_b = other.join().unwrap();
+
+            let last = buffer.snapshot().iter().copied().collect::<Vec<_>>();
+            match (evicted_a, evicted_b) {
+                (None, Some(evicted)) => assert_eq!((evicted, last), (a, vec![b])),
+                (Some(evicted), None) => assert_eq!((evicted, last), (b, vec![a])),
+                other => panic!("unexpected evictions {:?}", other),
+            }
+        });
+    }
+}
@@ -0,0 +1,258 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::circular_mean_accumulator::CircularMeanAccumulator;
+use crate::engine::Engine;
+use crate::process_event::{Event, EventInfo};
+use crate::snapshot::{read_snapshot_file, write_snapshot_file, SnapshotError};
+use crate::write_ahead_log::{LogError, LogRecord, WriteAheadLog};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "events.log";
+
+// An engine whose state lives in a directory: the last snapshot plus a write-ahead log of every
+// event ingested since. Events are logged before they reach the engine, so a crash loses none of
+// the events `ingest` has returned for. An event the engine rejects leaves it unchanged and is
+// taken back out of the log, so every logged event but a last one cut short by a crash must
+// replay successfully.
+pub struct DurableEngine<const D: usize = 2, S = CircularMeanAccumulator<D>> {
+    engine: Engine<D, S>,
+    log: WriteAheadLog,
+    directory: PathBuf,
+    // the last sequence number logged, or covered by the snapshot
+    sequence: u64,
+}
+
+impl<const D: usize, S: CentroidStrategy<D>> DurableEngine<D, S> {
+    // Loads the snapshot in `directory` if there is one, otherwise starts from `fresh()`, then
+    // replays the logged events the snapshot does not cover yet.
+    pub fn open(
+        directory: impl AsRef<Path>,
+        fresh: impl FnOnce() -> Engine<D, S>,
+    ) -> Result<Self, LogError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let snapshot_path = directory.join(SNAPSHOT_FILE);
+        let (mut engine, mut sequence) = if snapshot_path.exists() {
+            let snapshot = read_snapshot_file(&snapshot_path)?;
+            let sequence = snapshot["log_sequence"]
+                .as_u64()
+                .ok_or_else(|| SnapshotError::Invalid("log_sequence".to_string()))?;
+            (Engine::from_snapshot(&snapshot)?, sequence)
+        } else {
+            (fresh(), 0)
+        };
+
+        let (mut log, records) = WriteAheadLog::open(directory.join(LOG_FILE))?;
+        let last = records.len();
+        // records up to the snapshot's sequence are left over from a crash before compaction
+        for (position, record) in records.into_iter().enumerate() {
+            if record.sequence <= sequence {
+                continue;
+            }
+            match engine.ingest(&record.event) {
+                Ok(_) => sequence = record.sequence,
+                // the crash came before a rejected event could be taken back out of the log
+                Err(_) if position + 1 == last => log.discard_last()?,
+                Err(error) => return Err(LogError::Replay(record.sequence, error)),
+            }
+        }
+
+        Ok(DurableEngine {
+            engine,
+            log,
+            directory,
+            sequence,
+        })
+    }
+
+    pub fn ingest(&mut self, event: &Event) -> Result<EventInfo<D>, LogError> {
+        let record = LogRecord {
+            sequence: self.sequence + 1,
+            event: event.clone(),
+        };
+        self.log.append(&record)?;
+        match self.engine.ingest(event) {
+            Ok(info) => {
+                self.sequence = record.sequence;
+                Ok(info)
+            }
+            Err(error) => {
+                self.log.discard_last()?;
+                Err(error.into())
+            }
+        }
+    }
+
+    // Saves a snapshot covering every logged event, then compacts the log.
+    pub fn snapshot(&mut self) -> Result<(), LogError> {
+        let mut snapshot = self.engine.to_snapshot();
+        snapshot["log_sequence"] = json!(self.sequence);
+        write_snapshot_file(&self.directory.join(SNAPSHOT_FILE), &snapshot)?;
+        self.log.compact()
+    }
+
+    pub fn engine(&self) -> &Engine<D, S> {
+        &self.engine
+    }
+
+    // Mutable access is for settings such as the eviction policy; events ingested straight into
+    // the engine bypass the log and are lost on restart.
+    pub fn engine_mut(&mut self) -> &mut Engine<D, S> {
+        &mut self.engine
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::RefusesThirteen;
+    use crate::error::Error;
+    use crate::torus::Torus;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use uuid::Uuid;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("inverse-pairs-{}", Uuid::new_v4()))
+    }
+
+    fn fresh() -> Engine {
+        Engine::with_torus(4, Torus::new([1 << 16, 1 << 16]).unwrap())
+    }
+
+    fn ids(count: usize) -> Vec<Uuid> {
+        let pool: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        (0..count).map(|i| pool[i * 3 % pool.len()]).collect()
+    }
+
+    #[test]
+    fn test_durable_engine_replays_log_after_crash() {
+        let dir = temp_dir();
+        let ids = ids(50);
+        let mut reference = fresh();
+
+        {
+            let mut durable = DurableEngine::open(&dir, fresh).unwrap();
+            for id in &ids[..20] {
+                durable.ingest(&Event::new(*id)).unwrap();
+                reference.ingest(&Event::new(*id)).unwrap();
+            }
+            durable.snapshot().unwrap();
+            for id in &ids[20..] {
+                durable.ingest(&Event::new(*id)).unwrap();
+                reference.ingest(&Event::new(*id)).unwrap();
+            }
+            // dropped without a final snapshot, as in a crash
+        }
+
+        let reopened = DurableEngine::open(&dir, fresh).unwrap();
+        assert_eq!(reopened.sequence(), 50);
+        assert_eq!(reopened.engine().to_snapshot(), reference.to_snapshot());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_durable_engine_skips_records_covered_by_snapshot() {
+        let dir = temp_dir();
+        let ids = ids(12);
+        let log_before_compaction;
+        {
+            let mut durable = DurableEngine::open(&dir, fresh).unwrap();
+            for id in &ids {
+                durable.ingest(&Event::new(*id)).unwrap();
+            }
+            log_before_compaction = fs::read(dir.join(LOG_FILE)).unwrap();
+            durable.snapshot().unwrap();
+        }
+        let expected = DurableEngine::open(&dir, fresh)
+            .unwrap()
+            .engine()
+            .to_snapshot();
+
+        // a crash between writing the snapshot and compacting the log leaves both behind
+        fs::write(dir.join(LOG_FILE), &log_before_compaction).unwrap();
+        // and a torn record at the end of the log
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(&[25, 0, 0, 0, 1, 2]).unwrap();
+        drop(log);
+
+        let reopened = DurableEngine::open(&dir, fresh).unwrap();
+        assert_eq!(reopened.sequence(), 12);
+        assert_eq!(reopened.engine().to_snapshot(), expected);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn refusing() -> Engine<2, RefusesThirteen> {
+        Engine::with_strategy(4, RefusesThirteen(CircularMeanAccumulator::new()))
+    }
+
+    #[test]
+    fn test_durable_engine_takes_rejected_events_out_of_the_log() {
+        let dir = temp_dir();
+        let ids = ids(10);
+        let mut reference = refusing();
+        {
+            let mut durable = DurableEngine::open(&dir, refusing).unwrap();
+            for (i, id) in ids.iter().enumerate() {
+                let event = Event::new(*id).with_weight(if i % 4 == 1 { 13 } else { 1 });
+                let outcome = durable.ingest(&event).map_err(|error| error.to_string());
+                assert_eq!(
+                    outcome,
+                    reference.ingest(&event).map_err(|error| error.to_string())
+                );
+            }
+            assert_eq!(durable.sequence(), 7);
+        }
+
+        let reopened = DurableEngine::open(&dir, refusing).unwrap();
+        assert_eq!(reopened.sequence(), 7);
+        assert_eq!(reopened.engine().to_snapshot(), reference.to_snapshot());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_durable_engine_replays_rejections_only_at_the_end() {
+        let dir = temp_dir();
+        let id = Uuid::new_v4();
+        drop(DurableEngine::open(&dir, refusing).unwrap());
+        let accepted = fs::read(dir.join(LOG_FILE)).unwrap();
+        let rejected = |sequence| LogRecord {
+            sequence,
+            event: Event::new(id).with_weight(13),
+        };
+
+        // a crash before a rejected event was taken back out leaves it last in the log
+        let (mut log, _) = WriteAheadLog::open(dir.join(LOG_FILE)).unwrap();
+        log.append(&rejected(1)).unwrap();
+        drop(log);
+        let reopened = DurableEngine::open(&dir, refusing).unwrap();
+        assert_eq!(reopened.sequence(), 0);
+        assert_eq!(fs::read(dir.join(LOG_FILE)).unwrap(), accepted);
+        drop(reopened);
+
+        // anywhere else the log no longer matches what the engine accepted
+        let (mut log, _) = WriteAheadLog::open(dir.join(LOG_FILE)).unwrap();
+        log.append(&rejected(1)).unwrap();
+        log.append(&LogRecord {
+            sequence: 2,
+            event: Event::new(id),
+        })
+        .unwrap();
+        drop(log);
+        assert!(matches!(
+            DurableEngine::open(&dir, refusing),
+            Err(LogError::Replay(1, Error::Overflow))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
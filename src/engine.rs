@@ -0,0 +1,516 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::circular_mean_accumulator::CircularMeanAccumulator;
+use crate::error::Error;
+use crate::eviction_policy::{EvictionPolicy, EvictionReason, EvictionTracker};
+use crate::find_inverse_pairs::{find_inverse_pairs_indexed, InversePair, PairOptions};
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use crate::furthest_from_set::furthest_from_set;
+use crate::process_event::{process_event, Event, EventInfo};
+use crate::toroidal_grid_index::ToroidalGridIndex;
+use crate::toroidal_metric::{Euclidean, ToroidalMetric};
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const INDEX_CELLS_PER_AXIS: u32 = 64;
+
+// Called with every placement the eviction policy removes from the map.
+type EvictionHook<const D: usize> = Box<dyn FnMut(&Uuid, &EventInfo<D>, EvictionReason) + Send>;
+
+// Owns the window, the placements and the centroid so they stay in step across calls. The
+// centroid is the windowed circular mean unless another strategy is chosen.
+pub struct Engine<const D: usize = 2, S = CircularMeanAccumulator<D>> {
+    pub(crate) buffer: FixedCircularBuffer<Uuid>,
+    pub(crate) map: HashMap<Uuid, EventInfo<D>>,
+    pub(crate) centroid: S,
+    pub(crate) follow_index: ToroidalGridIndex<D>,
+    pub(crate) flee_index: ToroidalGridIndex<D>,
+    pub(crate) eviction_policy: EvictionPolicy,
+    pub(crate) evictions: EvictionTracker,
+    pub(crate) on_evict: Option<EvictionHook<D>>,
+}
+
+impl Engine {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_torus(capacity, Torus::FULL)
+    }
+}
+
+impl<const D: usize> Engine<D> {
+    pub fn with_torus(capacity: usize, torus: Torus<D>) -> Self {
+        Self::with_strategy(capacity, CircularMeanAccumulator::with_torus(torus))
+    }
+}
+
+impl<const D: usize, S: CentroidStrategy<D>> Engine<D, S> {
+    // Places events on the strategy's torus.
+    pub fn with_strategy(capacity: usize, centroid: S) -> Self {
+        let torus = *centroid.torus();
+        Engine {
+            buffer: FixedCircularBuffer::new(capacity),
+            map: HashMap::new(),
+            centroid,
+            follow_index: ToroidalGridIndex::new(torus, INDEX_CELLS_PER_AXIS),
+            flee_index: ToroidalGridIndex::new(torus, INDEX_CELLS_PER_AXIS),
+            eviction_policy: EvictionPolicy::default(),
+            evictions: EvictionTracker::default(),
+            on_evict: None,
+        }
+    }
+
+    pub fn torus(&self) -> &Torus<D> {
+        self.centroid.torus()
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.centroid
+    }
+
+    // Takes effect from the next ingest.
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.eviction_policy = policy;
+    }
+
+    pub fn eviction_policy(&self) -> &EvictionPolicy {
+        &self.eviction_policy
+    }
+
+    pub fn on_evict(
+        &mut self,
+        hook: impl FnMut(&Uuid, &EventInfo<D>, EvictionReason) + Send + 'static,
+    ) {
+        self.on_evict = Some(Box::new(hook));
+    }
+
+    // How many times `id` occurs in the buffer.
+    pub fn references(&self, id: &Uuid) -> usize {
+        self.evictions.references(id)
+    }
+
+    pub fn ingest(&mut self, event: &Event) -> Result<EventInfo<D>, Error> {
+        let pushed_out = process_event(event, &mut self.buffer, &mut self.map, &mut self.centroid)?;
+        let info = self
+            .map
+            .get(&event.id)
+            .copied()
+            .ok_or(Error::MissingId(event.id))?;
+
+        // process_event only ever places or moves the ingested id
+        self.follow_index.insert(event.id, info.follow);
+        self.flee_index.insert(event.id, info.flee);
+
+        self.evictions.record(event.id, pushed_out);
+        self.evict();
+        Ok(info)
+    }
+
+    // The ingested id is always in the buffer, so it is never among the evicted.
+    fn evict(&mut self) {
+        for (id, reason) in self.evictions.evict(&self.eviction_policy, self.map.len()) {
+            if let Some(info) = self.map.remove(&id) {
+                self.follow_index.remove(&id);
+                self.flee_index.remove(&id);
+                if let Some(hook) = &mut self.on_evict {
+                    hook(&id, &info, reason);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&EventInfo<D>> {
+        self.map.get(id)
+    }
+
+    pub fn flee_average(&self) -> Option<TorusPoint<D>> {
+        self.centroid.centroid()
+    }
+
+    // The k events whose follow points are closest to `point`, nearest first, with squared distances.
+    pub fn nearest_follows(&self, point: &TorusPoint<D>, k: usize) -> Vec<(Uuid, u128)> {
+        self.follow_index.nearest(point, k)
+    }
+
+    pub fn nearest_flees(&self, point: &TorusPoint<D>, k: usize) -> Vec<(Uuid, u128)> {
+        self.flee_index.nearest(point, k)
+    }
+
+    pub fn follows_within(&self, point: &TorusPoint<D>, radius_squared: u128) -> Vec<(Uuid, u128)> {
+        self.follow_index.within_radius(point, radius_squared)
+    }
+
+    pub fn flees_within(&self, point: &TorusPoint<D>, radius_squared: u128) -> Vec<(Uuid, u128)> {
+        self.flee_index.within_radius(point, radius_squared)
+    }
+
+    // The point furthest from every placed flee point, where a new event would be as far from all
+    // of them as it can get, rather than opposite their centroid.
+    pub fn furthest_from_flees<M: ToroidalMetric<D>>(
+        &self,
+        metric: &M,
+    ) -> Option<(TorusPoint<D>, u128)> {
+        let flees: Vec<TorusPoint<D>> = self.map.values().map(|info| info.flee).collect();
+        furthest_from_set(self.torus(), metric, &flees)
+    }
+
+    // The follow points of every placed event, for queries under other metrics.
+    pub fn follow_index(&self) -> &ToroidalGridIndex<D> {
+        &self.follow_index
+    }
+
+    pub fn flee_index(&self) -> &ToroidalGridIndex<D> {
+        &self.flee_index
+    }
+
+    // Ranked pairs of placed events where a's follow point meets b's flee point.
+    pub fn inverse_pairs(&self, options: &PairOptions) -> Vec<InversePair> {
+        self.inverse_pairs_by(&Euclidean, options)
+    }
+
+    pub fn inverse_pairs_by<M: ToroidalMetric<D>>(
+        &self,
+        metric: &M,
+        options: &PairOptions,
+    ) -> Vec<InversePair> {
+        find_inverse_pairs_indexed(self.torus(), &self.map, &self.flee_index, metric, options)
+    }
+
+    pub fn buffer(&self) -> &FixedCircularBuffer<Uuid> {
+        &self.buffer
+    }
+
+    pub fn map(&self) -> &HashMap<Uuid, EventInfo<D>> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_ingest_returns_placement() {
+        let mut engine = Engine::new(8);
+        let event = Event::new(Uuid::new_v4());
+
+        let info = engine.ingest(&event).unwrap();
+
+        assert_eq!(info.follow.x(), 1 << 31);
+        assert_eq!(info.follow.y(), 1 << 31);
+        assert_eq!(engine.get(&event.id), Some(&info));
+        assert_eq!(engine.buffer().front(), Some(&event.id));
+        assert_eq!(
+            engine.flee_average(),
+            Some(TorusPoint::new([1 << 31, 1 << 31]))
+        );
+    }
+
+    #[test]
+    fn test_engine_carries_rolling_average_between_calls() {
+        let mut engine = Engine::new(2);
+        let first = engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+        let second = engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+
+        // the second event is placed opposite the first one's flee point
+        assert_ne!(first.follow, second.follow);
+
+        let mut expected = CircularMeanAccumulator::new();
+        expected.add(&first.flee).unwrap();
+        expected.add(&second.flee).unwrap();
+        assert_eq!(engine.flee_average(), expected.mean());
+
+        // a third event evicts the first from the window and from the average
+        let third = engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+        let mut expected = CircularMeanAccumulator::new();
+        expected.add(&second.flee).unwrap();
+        expected.add(&third.flee).unwrap();
+        assert_eq!(engine.flee_average(), expected.mean());
+        assert_eq!(engine.map().len(), 3);
+    }
+
+    #[test]
+    fn test_engine_ingest_repeated_event() {
+        let mut engine = Engine::new(4);
+        let repeated = Event::new(Uuid::new_v4());
+        engine.ingest(&repeated).unwrap();
+        engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+
+        let moved = engine.ingest(&repeated).unwrap();
+
+        assert_eq!(engine.get(&repeated.id), Some(&moved));
+        assert_eq!(engine.buffer().len(), 3);
+        assert_eq!(engine.map().len(), 2);
+    }
+
+    #[test]
+    fn test_engine_nearest_queries_follow_moves() {
+        let mut engine = Engine::with_torus(4, Torus::new([1024, 1024]).unwrap());
+        let repeated = Event::new(Uuid::new_v4());
+        let other = Event::new(Uuid::new_v4());
+        engine.ingest(&repeated).unwrap();
+        let placed = engine.ingest(&other).unwrap();
+
+        assert_eq!(engine.nearest_follows(&placed.flee, 1), vec![(other.id, 0)]);
+
+        let moved = engine.ingest(&repeated).unwrap();
+        assert_eq!(
+            engine.nearest_follows(&moved.follow, 1),
+            vec![(repeated.id, 0)]
+        );
+        assert_eq!(engine.flees_within(&moved.flee, 0), vec![(repeated.id, 0)]);
+        assert_eq!(
+            engine.follows_within(&TorusPoint::new([512, 512]), 0),
+            vec![]
+        );
+        assert_eq!(engine.nearest_flees(&TorusPoint::ORIGIN, 5).len(), 2);
+    }
+
+    #[test]
+    fn test_engine_inverse_pairs_match_free_function() {
+        let mut engine = Engine::with_torus(3, Torus::new([4096, 4096]).unwrap());
+        for _ in 0..10 {
+            engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+        }
+        let options = PairOptions {
+            top_k: Some(5),
+            ..PairOptions::default()
+        };
+
+        assert_eq!(
+            engine.inverse_pairs(&options),
+            crate::find_inverse_pairs(engine.torus(), engine.map(), &options)
+        );
+        assert_eq!(
+            engine.inverse_pairs_by(&crate::Manhattan, &options),
+            crate::find_inverse_pairs_by(engine.torus(), engine.map(), &crate::Manhattan, &options)
+        );
+    }
+
+    #[test]
+    fn test_engine_furthest_from_flees() {
+        let mut engine = Engine::with_torus(4, Torus::new([64, 64]).unwrap());
+        assert_eq!(engine.furthest_from_flees(&Euclidean), None);
+        for _ in 0..6 {
+            engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+        }
+
+        let (point, distance) = engine.furthest_from_flees(&Euclidean).unwrap();
+        assert!(distance > 0);
+        assert_eq!(engine.flee_index().nearest(&point, 1)[0].1, distance);
+    }
+
+    #[test]
+    fn test_engine_drops_unbuffered_entries_and_reports_them() {
+        use std::sync::{Arc, Mutex};
+
+        let mut engine = Engine::new(2);
+        engine.set_eviction_policy(EvictionPolicy {
+            drop_unbuffered: true,
+            ..EvictionPolicy::default()
+        });
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&evicted);
+        engine.on_evict(move |id, _, reason| sink.lock().unwrap().push((*id, reason)));
+
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        engine.ingest(&Event::new(a)).unwrap();
+        engine.ingest(&Event::new(a)).unwrap();
+        engine.ingest(&Event::new(b)).unwrap();
+        // one of a's two occurrences is still buffered
+        assert_eq!(engine.references(&a), 1);
+        assert!(engine.get(&a).is_some());
+
+        engine.ingest(&Event::new(c)).unwrap();
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(a, EvictionReason::Unbuffered)]
+        );
+        assert_eq!(engine.get(&a), None);
+        assert_eq!(engine.map().len(), 2);
+        assert!(engine
+            .nearest_flees(&TorusPoint::ORIGIN, 5)
+            .iter()
+            .all(|(id, _)| *id != a));
+    }
+
+    #[test]
+    fn test_engine_max_entries_bounds_the_map() {
+        let mut engine = Engine::new(4);
+        engine.set_eviction_policy(EvictionPolicy {
+            max_entries: Some(10),
+            ..EvictionPolicy::default()
+        });
+
+        for _ in 0..1_000 {
+            engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+            assert!(engine.map().len() <= 10);
+        }
+        // the window can still be averaged because buffered ids are never evicted
+        for id in engine.buffer() {
+            assert!(engine.get(id).is_some());
+        }
+    }
+
+    #[test]
+    fn test_engine_with_exponential_strategy() {
+        use crate::exponential_mean::{ExponentialMean, HalfLife};
+
+        let torus = Torus::new([1024, 1024]).unwrap();
+        let mut engine = Engine::with_strategy(
+            2,
+            ExponentialMean::new(torus, HalfLife::Events(8.0)).unwrap(),
+        );
+        let mut windowed = Engine::with_torus(2, torus);
+        let ids: Vec<Uuid> = (0..6).map(|_| Uuid::new_v4()).collect();
+
+        for id in &ids {
+            engine.ingest(&Event::new(*id)).unwrap();
+            windowed.ingest(&Event::new(*id)).unwrap();
+        }
+
+        // the decayed mean still remembers events the two-slot window has dropped
+        assert_eq!(engine.strategy().half_life(), HalfLife::Events(8.0));
+        assert_ne!(engine.flee_average(), windowed.flee_average());
+        assert_eq!(engine.map().len(), 6);
+    }
+
+    #[test]
+    fn test_engine_refuses_untimestamped_repeats_with_half_life_in_time() {
+        use crate::exponential_mean::{ExponentialMean, HalfLife};
+
+        let torus = Torus::new([1024, 1024]).unwrap();
+        let mut engine = Engine::with_strategy(
+            4,
+            ExponentialMean::new(torus, HalfLife::Millis(1_000)).unwrap(),
+        );
+        let (early, late) = (Uuid::new_v4(), Uuid::new_v4());
+        engine
+            .ingest(&Event::new(early).with_timestamp(5_000))
+            .unwrap();
+        engine
+            .ingest(&Event::new(late).with_timestamp(9_000))
+            .unwrap();
+
+        // the repeat must not pass as happening at its earlier event's time
+        let before = engine.to_snapshot();
+        assert_eq!(
+            engine.ingest(&Event::new(early)),
+            Err(Error::MissingTimestamp(early))
+        );
+        assert_eq!(engine.to_snapshot(), before);
+
+        let repeat = engine
+            .ingest(&Event::new(early).with_timestamp(10_000))
+            .unwrap();
+        assert_eq!(repeat.timestamp, Some(10_000));
+        // five and one half lives after the first two events, which still count
+        assert_eq!(
+            engine.strategy().total_weight(),
+            1.0 / 32.0 + 1.0 / 2.0 + 1.0
+        );
+    }
+
+    #[test]
+    fn test_engine_on_small_torus() {
+        let mut engine = Engine::with_torus(4, Torus::new([1024, 512]).unwrap());
+        let first = engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+        let second = engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+
+        assert_eq!(first.follow, TorusPoint::new([512, 256]));
+        assert_eq!(second.follow, TorusPoint::ORIGIN);
+    }
+
+    #[test]
+    fn test_engine_in_eight_dimensions() {
+        let mut engine = Engine::<8>::with_torus(4, Torus::FULL);
+        let repeated = Event::new(Uuid::new_v4());
+        let first = engine.ingest(&repeated).unwrap();
+        let second = engine.ingest(&Event::new(Uuid::new_v4())).unwrap();
+
+        assert_eq!(first.follow, TorusPoint::new([1 << 31; 8]));
+        assert_eq!(second.follow, TorusPoint::ORIGIN);
+
+        // the repeat moves half way towards the other flee point on every axis
+        let moved = engine.ingest(&repeated).unwrap();
+        assert_eq!(moved.follow, TorusPoint::new([3 << 30; 8]));
+        assert_eq!(engine.buffer().len(), 3);
+    }
+
+    // The windowed mean, except that it refuses to admit events of weight 13.
+    #[derive(Clone)]
+    pub(crate) struct RefusesThirteen(pub(crate) CircularMeanAccumulator);
+
+    impl CentroidStrategy<2> for RefusesThirteen {
+        const NAME: &'static str = "refuses-thirteen";
+
+        fn torus(&self) -> &Torus {
+            self.0.torus()
+        }
+
+        fn admit(
+            &mut self,
+            event_map: &HashMap<Uuid, EventInfo>,
+            event_buffer: &FixedCircularBuffer<Uuid>,
+            latest_uuid: &Uuid,
+        ) -> Result<(), Error> {
+            match event_map.get(latest_uuid) {
+                Some(latest) if latest.weight == 13 => Err(Error::Overflow),
+                _ => self.0.admit(event_map, event_buffer, latest_uuid),
+            }
+        }
+
+        fn moved(
+            &mut self,
+            old: &EventInfo,
+            new: &EventInfo,
+            occurrences: usize,
+        ) -> Result<(), Error> {
+            self.0.moved(old, new, occurrences)
+        }
+
+        fn centroid(&self) -> Option<TorusPoint> {
+            self.0.centroid()
+        }
+
+        fn to_snapshot(&self) -> serde_json::Value {
+            self.0.to_snapshot()
+        }
+
+        fn from_snapshot(
+            torus: Torus,
+            snapshot: &serde_json::Value,
+        ) -> Result<Self, crate::snapshot::SnapshotError> {
+            CircularMeanAccumulator::from_snapshot(torus, snapshot).map(RefusesThirteen)
+        }
+    }
+
+    #[test]
+    fn test_engine_rejected_event_changes_nothing() {
+        let mut engine = Engine::with_strategy(3, RefusesThirteen(CircularMeanAccumulator::new()));
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            engine.ingest(&Event::new(*id)).unwrap();
+        }
+
+        // a new id, and a repeat whose move is already applied to the sums when admit fails
+        for event in [Event::new(Uuid::new_v4()), Event::new(ids[3])] {
+            let before = engine.to_snapshot();
+            assert_eq!(
+                engine.ingest(&event.clone().with_weight(13)),
+                Err(Error::Overflow)
+            );
+            assert_eq!(engine.to_snapshot(), before);
+            assert_eq!(engine.follow_index().len(), 4);
+            assert_eq!(engine.references(&ids[3]), 1);
+        }
+
+        // so the same events go through as if the rejected ones never happened
+        let mut reference = Engine::new(3);
+        for id in &ids {
+            reference.ingest(&Event::new(*id)).unwrap();
+        }
+        let event = Event::new(ids[3]).with_weight(2);
+        assert_eq!(engine.ingest(&event), reference.ingest(&event));
+        assert_eq!(engine.flee_average(), reference.flee_average());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::circular_mean_accumulator::CircularMeanAccumulator;
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::process_event::{Event, EventInfo};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use tokio::sync::{mpsc, oneshot};
+
+type Request<const D: usize> = (Event, oneshot::Sender<Result<EventInfo<D>, Error>>);
+
+#[derive(Debug)]
+pub enum HandleError {
+    Engine(Error),
+    // the queue is full; the event is handed back so it can be retried
+    Full(Event),
+    // the engine's worker is gone, after a shutdown or a panic
+    Stopped,
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::Engine(error) => write!(f, "{}", error),
+            HandleError::Full(event) => {
+                write!(f, "the queue is full, event {} was not sent", event.id)
+            }
+            HandleError::Stopped => write!(f, "the engine has stopped"),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HandleError::Engine(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for HandleError {
+    fn from(error: Error) -> Self {
+        HandleError::Engine(error)
+    }
+}
+
+// Feeds an engine from async code. The engine runs on a thread of its own, so ingestion never
+// blocks the runtime, and takes events from a bounded queue in the order they were sent: when
+// the queue is full, `enqueue` waits for room and `try_enqueue` hands the event back.
+pub struct EngineHandle<const D: usize = 2, S = CircularMeanAccumulator<D>> {
+    requests: mpsc::Sender<Request<D>>,
+    stopped: oneshot::Receiver<Engine<D, S>>,
+}
+
+// The placement of an enqueued event, ready once the engine has ingested it.
+pub struct Placement<const D: usize = 2> {
+    reply: oneshot::Receiver<Result<EventInfo<D>, Error>>,
+}
+
+impl<const D: usize> Future for Placement<D> {
+    type Output = Result<EventInfo<D>, HandleError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.reply).poll(cx).map(|reply| match reply {
+            Ok(placement) => placement.map_err(HandleError::Engine),
+            Err(_) => Err(HandleError::Stopped),
+        })
+    }
+}
+
+impl<const D: usize, S: CentroidStrategy<D> + Send + 'static> EngineHandle<D, S> {
+    // Starts a worker for `engine` that queues up to `queue` events; a queue of 0 holds one.
+    pub fn spawn(mut engine: Engine<D, S>, queue: usize) -> Self {
+        let (requests, mut pending) = mpsc::channel::<Request<D>>(queue.max(1));
+        let (stop, stopped) = oneshot::channel();
+        thread::spawn(move || {
+            // runs until every handle is gone and the queue is drained
+            while let Some((event, reply)) = pending.blocking_recv() {
+                let _ = reply.send(engine.ingest(&event));
+            }
+            let _ = stop.send(engine);
+        });
+        EngineHandle { requests, stopped }
+    }
+
+    pub async fn ingest(&self, event: Event) -> Result<EventInfo<D>, HandleError> {
+        self.enqueue(event).await?.await
+    }
+
+    // Waits for room in the queue, then returns the placement to await separately, so a caller
+    // can keep many events in flight.
+    pub async fn enqueue(&self, event: Event) -> Result<Placement<D>, HandleError> {
+        let (reply, placement) = oneshot::channel();
+        self.requests
+            .send((event, reply))
+            .await
+            .map_err(|_| HandleError::Stopped)?;
+        Ok(Placement { reply: placement })
+    }
+
+    pub fn try_enqueue(&self, event: Event) -> Result<Placement<D>, HandleError> {
+        let (reply, placement) = oneshot::channel();
+        self.requests
+            .try_send((event, reply))
+            .map_err(|error| match error {
+                mpsc::error::TrySendError::Full((event, _)) => HandleError::Full(event),
+                mpsc::error::TrySendError::Closed(_) => HandleError::Stopped,
+            })?;
+        Ok(Placement { reply: placement })
+    }
+
+    // How many more events fit in the queue right now.
+    pub fn room(&self) -> usize {
+        self.requests.capacity()
+    }
+
+    // Stops taking events, lets the worker ingest everything already queued, settling every
+    // outstanding placement, and returns the engine.
+    pub async fn shutdown(self) -> Result<Engine<D, S>, HandleError> {
+        let EngineHandle { requests, stopped } = self;
+        drop(requests);
+        stopped.await.map_err(|_| HandleError::Stopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eviction_policy::EvictionPolicy;
+    use crate::torus::Torus;
+    use std::sync::{mpsc as std_mpsc, Mutex};
+    use uuid::Uuid;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn engine() -> Engine {
+        Engine::with_torus(4, Torus::new([1 << 16, 1 << 16]).unwrap())
+    }
+
+    #[test]
+    fn test_handle_places_like_engine() {
+        let events: Vec<Event> = (0..20).map(|_| Event::new(Uuid::new_v4())).collect();
+        let mut reference = engine();
+
+        block_on(async {
+            let handle = EngineHandle::spawn(engine(), 4);
+            for event in &events {
+                assert_eq!(
+                    handle.ingest(event.clone()).await.unwrap(),
+                    reference.ingest(event).unwrap()
+                );
+            }
+            let engine = handle.shutdown().await.unwrap();
+            assert_eq!(engine.flee_average(), reference.flee_average());
+        });
+    }
+
+    #[test]
+    fn test_try_enqueue_reports_full_queue() {
+        let mut engine = Engine::with_torus(1, Torus::new([1024, 1024]).unwrap());
+        engine.set_eviction_policy(EvictionPolicy {
+            drop_unbuffered: true,
+            ..EvictionPolicy::default()
+        });
+        // every eviction parks the worker until the gate opens
+        let (entered, worker_parked) = std_mpsc::channel();
+        let (open, gate) = std_mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        engine.on_evict(move |_, _, _| {
+            let _ = entered.send(());
+            let _ = gate.lock().unwrap().recv();
+        });
+
+        block_on(async {
+            let handle = EngineHandle::spawn(engine, 1);
+            let events: Vec<Event> = (0..4).map(|_| Event::new(Uuid::new_v4())).collect();
+
+            handle.ingest(events[0].clone()).await.unwrap();
+            let second = handle.enqueue(events[1].clone()).await.unwrap();
+            // the second event evicts the first, so the worker is parked with an empty queue
+            worker_parked.recv().unwrap();
+
+            let third = handle.try_enqueue(events[2].clone()).unwrap();
+            assert_eq!(handle.room(), 0);
+            match handle.try_enqueue(events[3].clone()) {
+                Err(HandleError::Full(event)) => assert_eq!(event, events[3]),
+                _ => panic!("expected a full queue"),
+            }
+
+            drop(open);
+            assert!(second.await.is_ok());
+            assert!(third.await.is_ok());
+            assert_eq!(handle.room(), 1);
+        });
+    }
+
+    #[test]
+    fn test_shutdown_flushes_queued_events() {
+        block_on(async {
+            let handle = EngineHandle::spawn(engine(), 64);
+            let events: Vec<Event> = (0..50).map(|_| Event::new(Uuid::new_v4())).collect();
+            let mut placements = Vec::new();
+            for event in &events {
+                placements.push(handle.enqueue(event.clone()).await.unwrap());
+            }
+
+            let engine = handle.shutdown().await.unwrap();
+            assert!(events.iter().all(|event| engine.get(&event.id).is_some()));
+            for (event, placement) in events.iter().zip(placements) {
+                assert_eq!(placement.await.unwrap(), *engine.get(&event.id).unwrap());
+            }
+        });
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    // the window holds no flee points to average
+    EmptyWindow,
+    // an id in the window has no entry in the map
+    MissingId(Uuid),
+    // a zero-capacity buffer can never hold the window
+    ZeroCapacity,
+    // a running sum or count no longer fits its accumulator
+    Overflow,
+    // each axis of a torus must hold between 1 and 2^32 points
+    InvalidTorus { axis: usize, size: u64 },
+    // a half-life must be a positive, finite span
+    InvalidHalfLife,
+    // a half-life in time can only decay between events that carry a timestamp
+    MissingTimestamp(Uuid),
+    // an event of weight zero would not pull the average at all, and a window of them has none
+    ZeroWeight(Uuid),
+    // a `ShardedEngine` worker thread is no longer running
+    ShardStopped,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::EmptyWindow => write!(f, "the event window is empty"),
+            Error::MissingId(id) => write!(f, "event {} is missing from the map", id),
+            Error::ZeroCapacity => write!(f, "the event buffer has zero capacity"),
+            Error::Overflow => write!(f, "arithmetic overflow in the rolling average"),
+            Error::InvalidTorus { axis, size } => {
+                write!(f, "invalid torus size {} on axis {}", size, axis)
+            }
+            Error::InvalidHalfLife => write!(f, "the half-life must be positive and finite"),
+            Error::MissingTimestamp(id) => write!(f, "event {} has no timestamp", id),
+            Error::ZeroWeight(id) => write!(f, "event {} has zero weight", id),
+            Error::ShardStopped => write!(f, "a shard worker has stopped"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let id = Uuid::parse_str("fa84077a-7a27-48cf-b6f4-0becc82b09ac").unwrap();
+        assert_eq!(
+            Error::MissingId(id).to_string(),
+            "event fa84077a-7a27-48cf-b6f4-0becc82b09ac is missing from the map"
+        );
+        assert_eq!(Error::EmptyWindow.to_string(), "the event window is empty");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::process_event::{Event, EventInfo};
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+// The JSON shapes of events and placements shared by the servers, the socket client and
+// snapshots. The command line tool reads events in this shape too, but writes placements as flat
+// follow_x, follow_y, flee_x and flee_y fields, the same columns as its CSV output.
+impl Event {
+    // An object with a string "id" and optional "timestamp", "weight" and "payload" fields; any
+    // other fields are ignored. A string payload is taken as is and any other JSON value as its
+    // text, unless the payload is given as "payload_bytes", an array of byte values instead. The
+    // error names the offending field.
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let id = value
+            .get("id")
+            .and_then(Value::as_str)
+            .ok_or("JSON event has no string \"id\" field")?;
+        let mut event = Event::new(
+            Uuid::parse_str(id).map_err(|error| format!("invalid id {}: {}", id, error))?,
+        );
+
+        if let Some(timestamp) = value.get("timestamp").filter(|field| !field.is_null()) {
+            event.timestamp = Some(
+                timestamp
+                    .as_u64()
+                    .ok_or("\"timestamp\" must be an unsigned integer")?,
+            );
+        }
+        if let Some(weight) = value.get("weight").filter(|field| !field.is_null()) {
+            event.weight = weight
+                .as_u64()
+                .and_then(|weight| u32::try_from(weight).ok())
+                .filter(|weight| *weight > 0)
+                .ok_or("\"weight\" must be a positive 32-bit integer")?;
+        }
+        match value.get("payload") {
+            None | Some(Value::Null) => {}
+            Some(Value::String(payload)) => event.payload = payload.clone().into_bytes(),
+            Some(payload) => event.payload = payload.to_string().into_bytes(),
+        }
+        if let Some(bytes) = value.get("payload_bytes").filter(|field| !field.is_null()) {
+            if value.get("payload").is_some_and(|field| !field.is_null()) {
+                return Err("only one of \"payload\" and \"payload_bytes\" may be given".into());
+            }
+            event.payload = bytes
+                .as_array()
+                .and_then(|bytes| {
+                    bytes
+                        .iter()
+                        .map(|byte| byte.as_u64()?.try_into().ok())
+                        .collect::<Option<Vec<u8>>>()
+                })
+                .ok_or("\"payload_bytes\" must be an array of byte values")?;
+        }
+
+        Ok(event)
+    }
+
+    // The object `from_json` reads. A payload that is not UTF-8 cannot be a JSON string, so it
+    // goes as "payload_bytes" instead and reads back byte for byte.
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "id": self.id.to_string(),
+            "timestamp": self.timestamp,
+            "weight": self.weight,
+        });
+        match std::str::from_utf8(&self.payload) {
+            Ok(payload) => value["payload"] = json!(payload),
+            Err(_) => value["payload_bytes"] = json!(self.payload),
+        }
+        value
+    }
+}
+
+impl<const D: usize> EventInfo<D> {
+    // Reads back what `to_json` wrote, with the id it was written for.
+    pub fn from_json(value: &Value) -> Result<(Uuid, Self), String> {
+        let id = value
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or("placement has no valid \"id\" field")?;
+        let point = |field: &str| {
+            value
+                .get(field)
+                .and_then(Value::as_array)
+                .and_then(|coordinates| {
+                    coordinates
+                        .iter()
+                        .map(|coordinate| coordinate.as_u64()?.try_into().ok())
+                        .collect::<Option<Vec<u32>>>()
+                })
+                .and_then(|coordinates| coordinates.try_into().ok())
+                .map(TorusPoint::new)
+                .ok_or(format!(
+                    "placement has no {}-dimensional \"{}\" point",
+                    D, field
+                ))
+        };
+        let weight = value
+            .get("weight")
+            .and_then(Value::as_u64)
+            .and_then(|weight| u32::try_from(weight).ok())
+            .ok_or("placement has no valid \"weight\" field")?;
+        let timestamp = match value.get("timestamp") {
+            None | Some(Value::Null) => None,
+            Some(timestamp) => Some(
+                timestamp
+                    .as_u64()
+                    .ok_or("\"timestamp\" must be an unsigned integer")?,
+            ),
+        };
+
+        Ok((
+            id,
+            EventInfo {
+                follow: point("follow")?,
+                flee: point("flee")?,
+                weight,
+                timestamp,
+            },
+        ))
+    }
+
+    pub fn to_json(&self, id: &Uuid) -> Value {
+        json!({
+            "id": id.to_string(),
+            "follow": self.follow.coordinates.to_vec(),
+            "flee": self.flee.coordinates.to_vec(),
+            "weight": self.weight,
+            "timestamp": self.timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_from_json() {
+        let id = Uuid::new_v4();
+        assert_eq!(
+            Event::from_json(&json!({ "id": id.to_string(), "kind": "x", "weight": null })),
+            Ok(Event::new(id))
+        );
+        assert_eq!(
+            Event::from_json(&json!({ "id": id.to_string(), "payload": { "a": 1 } })),
+            Ok(Event::new(id).with_payload(r#"{"a":1}"#))
+        );
+        assert!(Event::from_json(&json!({ "id": id.to_string(), "timestamp": "now" })).is_err());
+        assert!(Event::from_json(&json!({ "id": id.to_string(), "weight": 0 })).is_err());
+        assert!(Event::from_json(&json!({ "id": 7 })).is_err());
+        assert!(Event::from_json(&json!([id.to_string()])).is_err());
+
+        let event = Event::new(id)
+            .with_timestamp(3)
+            .with_weight(4)
+            .with_payload("abc");
+        assert_eq!(Event::from_json(&event.to_json()), Ok(event));
+    }
+
+    #[test]
+    fn test_event_payload_bytes_round_trip() {
+        let id = Uuid::new_v4();
+        let event = Event::new(id).with_payload(vec![0xff, 0, b'a', 0xc3]);
+        let value = event.to_json();
+        assert_eq!(value["payload_bytes"], json!([255, 0, 97, 195]));
+        assert_eq!(value.get("payload"), None);
+        assert_eq!(Event::from_json(&value), Ok(event));
+
+        let bytes = |payload_bytes: Value| {
+            Event::from_json(&json!({ "id": id.to_string(), "payload_bytes": payload_bytes }))
+        };
+        assert!(bytes(json!([256])).is_err());
+        assert!(bytes(json!("abc")).is_err());
+        assert!(Event::from_json(
+            &json!({ "id": id.to_string(), "payload": "a", "payload_bytes": [97] })
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_event_info_json_round_trip() {
+        let id = Uuid::new_v4();
+        let mut info = EventInfo::new(TorusPoint::new([1, 2]), TorusPoint::new([3, 4]));
+        info.timestamp = Some(9);
+        assert_eq!(
+            info.to_json(&id),
+            json!({
+                "id": id.to_string(),
+                "follow": [1, 2],
+                "flee": [3, 4],
+                "weight": 1,
+                "timestamp": 9,
+            })
+        );
+        assert_eq!(EventInfo::from_json(&info.to_json(&id)), Ok((id, info)));
+        assert!(EventInfo::<3>::from_json(&info.to_json(&id)).is_err());
+    }
+}
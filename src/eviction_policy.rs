@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+// When placements may leave the map. Ids still in the buffer are never evicted, because the
+// rolling average and the repeat shift read their placements; every limit applies to idle ids only.
+// The default policy keeps every placement forever.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvictionPolicy {
+    // drop a placement as soon as its last occurrence leaves the buffer
+    pub drop_unbuffered: bool,
+    // drop idle placements not ingested within this many events
+    pub ttl: Option<u64>,
+    // drop the least recently ingested idle placements while the map holds more than this
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionReason {
+    Unbuffered,
+    Expired,
+    MaxEntries,
+}
+
+// Counts how often each id occurs in the buffer and orders the ids that no longer occur in it by
+// when they were last ingested, so every policy can pick its victims without scanning the map.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EvictionTracker {
+    tick: u64,
+    references: HashMap<Uuid, usize>,
+    last_ingested: HashMap<Uuid, u64>,
+    idle: BTreeMap<u64, Uuid>,
+}
+
+impl EvictionTracker {
+    // Rebuilds the tracker from what a snapshot keeps: the clock, when each placed id was last
+    // ingested and the buffer contents the reference counts follow from.
+    pub fn restore<'a>(
+        tick: u64,
+        last_ingested: HashMap<Uuid, u64>,
+        buffered: impl IntoIterator<Item = &'a Uuid>,
+    ) -> Self {
+        let mut references: HashMap<Uuid, usize> = HashMap::new();
+        for id in buffered {
+            *references.entry(*id).or_default() += 1;
+        }
+        let idle = last_ingested
+            .iter()
+            .filter(|(id, _)| !references.contains_key(id))
+            .map(|(id, ingested_at)| (*ingested_at, *id))
+            .collect();
+        EvictionTracker {
+            tick,
+            references,
+            last_ingested,
+            idle,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn last_ingested(&self) -> &HashMap<Uuid, u64> {
+        &self.last_ingested
+    }
+
+    pub fn references(&self, id: &Uuid) -> usize {
+        self.references.get(id).copied().unwrap_or(0)
+    }
+
+    // Records that `ingested` was pushed onto the buffer, pushing `pushed_out` off its back.
+    pub fn record(&mut self, ingested: Uuid, pushed_out: Option<Uuid>) {
+        self.tick += 1;
+
+        if let Some(previous) = self.last_ingested.insert(ingested, self.tick) {
+            self.idle.remove(&previous);
+        }
+        *self.references.entry(ingested).or_default() += 1;
+
+        if let Some(id) = pushed_out {
+            if let Some(count) = self.references.get_mut(&id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.references.remove(&id);
+                    self.idle.insert(self.last_ingested[&id], id);
+                }
+            }
+        }
+    }
+
+    // Picks the idle ids `policy` says must go from a map of `entries` placements and forgets them.
+    pub fn evict(
+        &mut self,
+        policy: &EvictionPolicy,
+        mut entries: usize,
+    ) -> Vec<(Uuid, EvictionReason)> {
+        let mut evicted = Vec::new();
+
+        while let Some((&ingested_at, &id)) = self.idle.first_key_value() {
+            let reason = if policy.drop_unbuffered {
+                EvictionReason::Unbuffered
+            } else if policy.ttl.is_some_and(|ttl| self.tick - ingested_at >= ttl) {
+                EvictionReason::Expired
+            } else if policy.max_entries.is_some_and(|max| entries > max) {
+                EvictionReason::MaxEntries
+            } else {
+                break;
+            };
+
+            self.idle.remove(&ingested_at);
+            self.last_ingested.remove(&id);
+            entries = entries.saturating_sub(1);
+            evicted.push((id, reason));
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_counts_references() {
+        let mut tracker = EvictionTracker::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        tracker.record(a, None);
+        tracker.record(a, None);
+        tracker.record(b, Some(a));
+        assert_eq!(tracker.references(&a), 1);
+        assert_eq!(tracker.references(&b), 1);
+
+        let policy = EvictionPolicy {
+            drop_unbuffered: true,
+            ..EvictionPolicy::default()
+        };
+        assert!(tracker.evict(&policy, 2).is_empty());
+
+        tracker.record(b, Some(a));
+        assert_eq!(tracker.references(&a), 0);
+        assert_eq!(
+            tracker.evict(&policy, 2),
+            vec![(a, EvictionReason::Unbuffered)]
+        );
+    }
+
+    #[test]
+    fn test_tracker_ttl_and_max_entries_evict_least_recent_first() {
+        let mut tracker = EvictionTracker::default();
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+
+        // with a one-slot buffer each id goes idle as soon as the next one arrives
+        tracker.record(ids[0], None);
+        tracker.record(ids[1], Some(ids[0]));
+        tracker.record(ids[2], Some(ids[1]));
+        tracker.record(ids[3], Some(ids[2]));
+
+        let max_entries = EvictionPolicy {
+            max_entries: Some(3),
+            ..EvictionPolicy::default()
+        };
+        assert_eq!(
+            tracker.evict(&max_entries, 4),
+            vec![(ids[0], EvictionReason::MaxEntries)]
+        );
+
+        let ttl = EvictionPolicy {
+            ttl: Some(2),
+            ..EvictionPolicy::default()
+        };
+        assert_eq!(
+            tracker.evict(&ttl, 3),
+            vec![(ids[1], EvictionReason::Expired)]
+        );
+
+        // ingesting an idle id again makes it recent and buffered
+        tracker.record(ids[2], Some(ids[3]));
+        assert!(tracker.evict(&ttl, 2).is_empty());
+        tracker.record(ids[2], Some(ids[2]));
+        assert_eq!(
+            tracker.evict(&ttl, 2),
+            vec![(ids[3], EvictionReason::Expired)]
+        );
+        assert_eq!(tracker.references(&ids[2]), 1);
+    }
+}
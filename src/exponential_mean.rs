@@ -0,0 +1,333 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::circular_mean_accumulator::from_angle;
+use crate::error::Error;
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use crate::process_event::EventInfo;
+use crate::snapshot::SnapshotError;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HalfLife {
+    // an event's pull halves after this many newer events
+    Events(f64),
+    // an event's pull halves after this many milliseconds, measured between event timestamps;
+    // events without one are rejected, since a clock reading would not replay the same
+    Millis(u64),
+}
+
+// A circular mean where every admitted flee point decays exponentially instead of dropping out
+// of a window, so an old event fades out gradually. The window size does not matter to it, and a
+// repeated event that moves only pulls the mean from where it lands next, since its old pull is
+// already part of the decayed history.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExponentialMean<const D: usize = 2> {
+    torus: Torus<D>,
+    half_life: HalfLife,
+    sum_cos: [f64; D],
+    sum_sin: [f64; D],
+    total_weight: f64,
+    last_timestamp: Option<u64>,
+}
+
+impl HalfLife {
+    // A zero span would decay by NaN, and a negative one would make old events grow.
+    fn validate(self) -> Result<Self, Error> {
+        match self {
+            HalfLife::Events(events) if !(events.is_finite() && events > 0.0) => {
+                Err(Error::InvalidHalfLife)
+            }
+            HalfLife::Millis(0) => Err(Error::InvalidHalfLife),
+            half_life => Ok(half_life),
+        }
+    }
+}
+
+impl<const D: usize> ExponentialMean<D> {
+    pub fn new(torus: Torus<D>, half_life: HalfLife) -> Result<Self, Error> {
+        Ok(ExponentialMean {
+            torus,
+            half_life: half_life.validate()?,
+            sum_cos: [0.0; D],
+            sum_sin: [0.0; D],
+            total_weight: 0.0,
+            last_timestamp: None,
+        })
+    }
+
+    pub fn half_life(&self) -> HalfLife {
+        self.half_life
+    }
+
+    // The decayed weight still pulling the mean.
+    pub fn total_weight(&self) -> f64 {
+        self.total_weight
+    }
+
+    // How much the sums decay before the latest event is added, and how much that event itself
+    // has decayed: one older than the newest seen so far arrives partly faded already.
+    fn decay_factors(&self, id: &Uuid, timestamp: Option<u64>) -> Result<(f64, f64), Error> {
+        match self.half_life {
+            HalfLife::Events(events) => Ok((0.5f64.powf(1.0 / events), 1.0)),
+            HalfLife::Millis(millis) => {
+                let now = timestamp.ok_or(Error::MissingTimestamp(*id))?;
+                let last = self.last_timestamp.unwrap_or(now);
+                let half_lives = |elapsed: u64| 0.5f64.powf(elapsed as f64 / millis as f64);
+                Ok((
+                    half_lives(now.saturating_sub(last)),
+                    half_lives(last.saturating_sub(now)),
+                ))
+            }
+        }
+    }
+
+    fn add(&mut self, point: &TorusPoint<D>, weight: f64) {
+        for (axis, size) in self.torus.sizes().iter().enumerate() {
+            let angle = (point[axis] as u64 % size) as f64 / *size as f64 * TAU;
+            self.sum_cos[axis] += weight * angle.cos();
+            self.sum_sin[axis] += weight * angle.sin();
+        }
+        self.total_weight += weight;
+    }
+}
+
+impl<const D: usize> CentroidStrategy<D> for ExponentialMean<D> {
+    const NAME: &'static str = "exponential";
+
+    fn torus(&self) -> &Torus<D> {
+        &self.torus
+    }
+
+    fn admit(
+        &mut self,
+        event_map: &HashMap<Uuid, EventInfo<D>>,
+        event_buffer: &FixedCircularBuffer<Uuid>,
+        latest_uuid: &Uuid,
+    ) -> Result<(), Error> {
+        if event_buffer.capacity() == 0 {
+            return Err(Error::ZeroCapacity);
+        }
+        let latest = event_map
+            .get(latest_uuid)
+            .ok_or(Error::MissingId(*latest_uuid))?;
+
+        let (factor, latest_factor) = self.decay_factors(latest_uuid, latest.timestamp)?;
+        for axis in 0..D {
+            self.sum_cos[axis] *= factor;
+            self.sum_sin[axis] *= factor;
+        }
+        self.total_weight *= factor;
+        self.add(&latest.flee, latest.weight as f64 * latest_factor);
+        if let HalfLife::Millis(_) = self.half_life {
+            self.last_timestamp = self.last_timestamp.max(latest.timestamp);
+        }
+        Ok(())
+    }
+
+    fn moved(&mut self, _: &EventInfo<D>, _: &EventInfo<D>, _: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn centroid(&self) -> Option<TorusPoint<D>> {
+        if self.total_weight <= 0.0 {
+            return None;
+        }
+        Some(TorusPoint::new(std::array::from_fn(|axis| {
+            from_angle(
+                self.sum_sin[axis],
+                self.sum_cos[axis],
+                self.torus.sizes()[axis],
+            )
+        })))
+    }
+
+    // floats are stored as their bits so a restored mean continues bit for bit
+    fn to_snapshot(&self) -> Value {
+        let bits = |values: &[f64; D]| {
+            values
+                .iter()
+                .map(|value| value.to_bits())
+                .collect::<Vec<_>>()
+        };
+        let half_life = match self.half_life {
+            HalfLife::Events(events) => json!({ "events": events.to_bits() }),
+            HalfLife::Millis(millis) => json!({ "millis": millis }),
+        };
+        json!({
+            "half_life": half_life,
+            "cos": bits(&self.sum_cos),
+            "sin": bits(&self.sum_sin),
+            "total_weight": self.total_weight.to_bits(),
+            "last_timestamp": self.last_timestamp,
+        })
+    }
+
+    fn from_snapshot(torus: Torus<D>, snapshot: &Value) -> Result<Self, SnapshotError> {
+        let invalid = || SnapshotError::Invalid("flee_sums".to_string());
+        let float = |value: &Value| value.as_u64().map(f64::from_bits).ok_or_else(invalid);
+        let floats = |field: &str| -> Result<[f64; D], SnapshotError> {
+            let values = snapshot[field]
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(float)
+                .collect::<Result<Vec<_>, _>>()?;
+            values.try_into().map_err(|_| invalid())
+        };
+
+        let half_life = &snapshot["half_life"];
+        let half_life = if let Some(millis) = half_life["millis"].as_u64() {
+            HalfLife::Millis(millis)
+        } else {
+            HalfLife::Events(float(&half_life["events"])?)
+        }
+        .validate()
+        .map_err(|_| SnapshotError::Invalid("half_life".to_string()))?;
+        let last_timestamp = match &snapshot["last_timestamp"] {
+            Value::Null => None,
+            value => Some(value.as_u64().ok_or_else(invalid)?),
+        };
+
+        Ok(ExponentialMean {
+            torus,
+            half_life,
+            sum_cos: floats("cos")?,
+            sum_sin: floats("sin")?,
+            total_weight: float(&snapshot["total_weight"])?,
+            last_timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admit_all<const D: usize>(
+        mean: &mut ExponentialMean<D>,
+        placements: &[EventInfo<D>],
+    ) -> Vec<Option<TorusPoint<D>>> {
+        let buffer = FixedCircularBuffer::new(1);
+        placements
+            .iter()
+            .map(|info| {
+                let id = Uuid::new_v4();
+                mean.admit(&HashMap::from([(id, *info)]), &buffer, &id)
+                    .unwrap();
+                mean.centroid()
+            })
+            .collect()
+    }
+
+    fn at(x: u32, timestamp: Option<u64>) -> EventInfo {
+        EventInfo {
+            timestamp,
+            ..EventInfo::new(TorusPoint::ORIGIN, TorusPoint::new([x, 0]))
+        }
+    }
+
+    #[test]
+    fn test_exponential_mean_half_life_in_events() {
+        let torus = Torus::new([360, 360]).unwrap();
+        let mut mean = ExponentialMean::new(torus, HalfLife::Events(1.0)).unwrap();
+        assert_eq!(mean.centroid(), None);
+
+        // after one newer event the first pulls half as hard: atan2(1, 0.5) is 63.4 degrees
+        let centroids = admit_all(&mut mean, &[at(0, None), at(90, None)]);
+        assert_eq!(centroids[0], Some(TorusPoint::new([0, 0])));
+        assert_eq!(centroids[1], Some(TorusPoint::new([63, 0])));
+        assert_eq!(mean.total_weight(), 1.5);
+    }
+
+    #[test]
+    fn test_exponential_mean_forgets_gradually() {
+        let torus = Torus::new([1000, 1000]).unwrap();
+        let mut mean = ExponentialMean::new(torus, HalfLife::Events(4.0)).unwrap();
+        let mut placements = vec![at(990, None); 20];
+        placements.extend(vec![at(200, None); 40]);
+
+        let centroids = admit_all(&mut mean, &placements);
+        // read across the seam, so 990 is -10
+        let x = |index: usize| {
+            let x = centroids[index].unwrap()[0] as i64;
+            if x > 500 {
+                x - 1000
+            } else {
+                x
+            }
+        };
+
+        // the old cluster fades steadily instead of vanishing after a fixed count
+        assert_eq!(x(19), -10);
+        assert!(x(22) > -10 && x(22) < 200);
+        assert!((19..59).all(|i| x(i) <= x(i + 1)));
+        assert_eq!(x(59), 200);
+    }
+
+    #[test]
+    fn test_exponential_mean_half_life_in_time() {
+        let torus = Torus::new([360, 360]).unwrap();
+        let mut mean = ExponentialMean::new(torus, HalfLife::Millis(1_000)).unwrap();
+
+        // a second apart is one half life, so this matches the event count test
+        let centroids = admit_all(&mut mean, &[at(0, Some(5_000)), at(90, Some(6_000))]);
+        assert_eq!(centroids[1], Some(TorusPoint::new([63, 0])));
+
+        // an event at the same instant does not decay anything
+        admit_all(&mut mean, &[at(90, Some(6_000))]);
+        assert_eq!(mean.total_weight(), 2.5);
+
+        // one a half life older than the newest counts half, and decays nothing else
+        admit_all(&mut mean, &[at(0, Some(5_000))]);
+        assert_eq!(mean.total_weight(), 3.0);
+
+        // and one without a timestamp is refused, leaving the mean as it was
+        let before = mean;
+        let id = Uuid::new_v4();
+        let buffer = FixedCircularBuffer::new(1);
+        assert_eq!(
+            mean.admit(&HashMap::from([(id, at(0, None))]), &buffer, &id),
+            Err(Error::MissingTimestamp(id))
+        );
+        assert_eq!(mean, before);
+    }
+
+    #[test]
+    fn test_exponential_mean_rejects_invalid_half_lives() {
+        let torus = Torus::new([360, 360]).unwrap();
+        for half_life in [
+            HalfLife::Events(0.0),
+            HalfLife::Events(-1.0),
+            HalfLife::Events(f64::NAN),
+            HalfLife::Events(f64::INFINITY),
+            HalfLife::Millis(0),
+        ] {
+            assert_eq!(
+                ExponentialMean::new(torus, half_life),
+                Err(Error::InvalidHalfLife),
+                "{:?}",
+                half_life
+            );
+        }
+
+        let mut snapshot = ExponentialMean::new(torus, HalfLife::Millis(10))
+            .unwrap()
+            .to_snapshot();
+        snapshot["half_life"] = json!({ "millis": 0 });
+        assert!(ExponentialMean::from_snapshot(torus, &snapshot).is_err());
+    }
+
+    #[test]
+    fn test_exponential_mean_snapshot_round_trip() {
+        let torus = Torus::new([1 << 20, 77]).unwrap();
+        let mut mean = ExponentialMean::new(torus, HalfLife::Events(2.5)).unwrap();
+        admit_all(&mut mean, &[at(5, None), at(700, None), at(90_000, None)]);
+
+        let restored = ExponentialMean::from_snapshot(torus, &mean.to_snapshot()).unwrap();
+        assert_eq!(restored, mean);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::process_event::EventInfo;
+use crate::toroidal_grid_index::ToroidalGridIndex;
+use crate::toroidal_metric::{Euclidean, ToroidalMetric};
+use crate::torus::Torus;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use uuid::Uuid;
+
+const INDEX_CELLS_PER_AXIS: u32 = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PairOptions {
+    // only pairs at most this far apart, in the metric's units (squared for Euclidean); every
+    // pair when unset
+    pub max_distance: Option<u128>,
+    // keep only the k best ranked pairs
+    pub top_k: Option<usize>,
+    // also require b's follow point to be near a's flee point, reporting each such pair once
+    pub reverse: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InversePair {
+    pub a: Uuid,
+    pub b: Uuid,
+    // from a's follow point to b's flee point
+    pub distance: u128,
+    // from b's follow point to a's flee point, set when the reverse was requested
+    pub reverse_distance: Option<u128>,
+}
+
+impl InversePair {
+    // The value pairs are ranked by, smallest first.
+    pub fn score(&self) -> u128 {
+        self.distance
+            .saturating_add(self.reverse_distance.unwrap_or(0))
+    }
+
+    // The score, with ties broken by id so the ranking is total.
+    fn rank(&self) -> (u128, Uuid, Uuid) {
+        (self.score(), self.a, self.b)
+    }
+}
+
+// A pair ordered by rank, so a max-heap of them has the worst ranked pair on top.
+struct Ranked(InversePair);
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.rank().cmp(&other.0.rank())
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+// Ranks the pairs (a, b) of distinct events where a's follow point is close to b's flee point,
+// by squared Euclidean distance.
+pub fn find_inverse_pairs<const D: usize>(
+    torus: &Torus<D>,
+    map: &HashMap<Uuid, EventInfo<D>>,
+    options: &PairOptions,
+) -> Vec<InversePair> {
+    find_inverse_pairs_by(torus, map, &Euclidean, options)
+}
+
+// As `find_inverse_pairs`, but measuring closeness with `metric`.
+pub fn find_inverse_pairs_by<const D: usize, M: ToroidalMetric<D>>(
+    torus: &Torus<D>,
+    map: &HashMap<Uuid, EventInfo<D>>,
+    metric: &M,
+    options: &PairOptions,
+) -> Vec<InversePair> {
+    let mut flee_index = ToroidalGridIndex::new(*torus, INDEX_CELLS_PER_AXIS);
+    for (id, info) in map {
+        flee_index.insert(*id, info.flee);
+    }
+    find_inverse_pairs_indexed(torus, map, &flee_index, metric, options)
+}
+
+// As `find_inverse_pairs`, but reuses an index that already holds the flee point of every id in `map`.
+pub(crate) fn find_inverse_pairs_indexed<const D: usize, M: ToroidalMetric<D>>(
+    torus: &Torus<D>,
+    map: &HashMap<Uuid, EventInfo<D>>,
+    flee_index: &ToroidalGridIndex<D>,
+    metric: &M,
+    options: &PairOptions,
+) -> Vec<InversePair> {
+    if options.top_k == Some(0) {
+        return Vec::new();
+    }
+    let max_distance = options.max_distance.unwrap_or(u128::MAX);
+
+    let mut pairs = Vec::new();
+    // with top_k, only the k best pairs so far are kept, the worst of them on top
+    let mut best = BinaryHeap::new();
+    for (a, a_info) in map {
+        // a pair further apart than the worst of k kept ones cannot outrank it
+        let radius = match (options.top_k, best.peek()) {
+            (Some(k), Some(Ranked(worst))) if best.len() == k => worst.score().min(max_distance),
+            _ => max_distance,
+        };
+        for (b, distance) in flee_index.within_radius_by(metric, &a_info.follow, radius) {
+            if b == *a {
+                continue;
+            }
+            let mut pair = InversePair {
+                a: *a,
+                b,
+                distance,
+                reverse_distance: None,
+            };
+
+            if options.reverse {
+                // the mirrored pair is found from b's side, so keep only one of the two
+                if b < *a {
+                    continue;
+                }
+                let Some(b_info) = map.get(&b) else {
+                    continue;
+                };
+                let reverse = metric.distance(torus, &b_info.follow, &a_info.flee);
+                if reverse > max_distance {
+                    continue;
+                }
+                pair.reverse_distance = Some(reverse);
+            }
+
+            match options.top_k {
+                Some(k) => {
+                    best.push(Ranked(pair));
+                    if best.len() > k {
+                        best.pop();
+                    }
+                }
+                None => pairs.push(pair),
+            }
+        }
+    }
+
+    if options.top_k.is_some() {
+        pairs = best.into_iter().map(|Ranked(pair)| pair).collect();
+    }
+    pairs.sort_by_key(InversePair::rank);
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toroidal_metric::{Chebyshev, Manhattan};
+    use crate::torus_point::TorusPoint;
+
+    fn info(follow: [u32; 2], flee: [u32; 2]) -> EventInfo {
+        EventInfo::new(TorusPoint::new(follow), TorusPoint::new(flee))
+    }
+
+    fn ids() -> (Uuid, Uuid, Uuid) {
+        let mut ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        ids.sort();
+        (ids[0], ids[1], ids[2])
+    }
+
+    #[test]
+    fn test_find_inverse_pairs_ranks_by_distance_across_seam() {
+        let torus = Torus::new([1024, 1024]).unwrap();
+        let (first, second, third) = ids();
+        let map = HashMap::from([
+            (first, info([1022, 0], [500, 500])),
+            (second, info([100, 100], [1, 2])),
+            (third, info([510, 500], [700, 700])),
+        ]);
+
+        let pairs = find_inverse_pairs(
+            &torus,
+            &map,
+            &PairOptions {
+                max_distance: Some(200),
+                ..PairOptions::default()
+            },
+        );
+
+        assert_eq!(
+            pairs,
+            vec![
+                InversePair {
+                    a: first,
+                    b: second,
+                    distance: 3 * 3 + 2 * 2,
+                    reverse_distance: None,
+                },
+                InversePair {
+                    a: third,
+                    b: first,
+                    distance: 10 * 10,
+                    reverse_distance: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_inverse_pairs_top_k_without_threshold() {
+        let torus = Torus::new([1024, 1024]).unwrap();
+        let (first, second, third) = ids();
+        let map = HashMap::from([
+            (first, info([0, 0], [0, 0])),
+            (second, info([10, 0], [10, 0])),
+            (third, info([600, 600], [600, 600])),
+        ]);
+
+        let all = find_inverse_pairs(&torus, &map, &PairOptions::default());
+        assert_eq!(all.len(), 6);
+        assert!(all.windows(2).all(|w| w[0].score() <= w[1].score()));
+
+        let best = find_inverse_pairs(
+            &torus,
+            &map,
+            &PairOptions {
+                top_k: Some(2),
+                ..PairOptions::default()
+            },
+        );
+        assert_eq!(
+            best.iter().map(|pair| (pair.a, pair.b)).collect::<Vec<_>>(),
+            vec![(first, second), (second, first)]
+        );
+    }
+
+    #[test]
+    fn test_find_inverse_pairs_top_k_matches_full_ranking() {
+        let torus = Torus::new([256, 256]).unwrap();
+        // a small linear congruential generator keeps the points spread but repeatable
+        let mut state = 12345u32;
+        let mut coordinate = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % 256
+        };
+        let map: HashMap<Uuid, EventInfo> = (0..150)
+            .map(|_| {
+                let follow = [coordinate(), coordinate()];
+                (Uuid::new_v4(), info(follow, [coordinate(), coordinate()]))
+            })
+            .collect();
+
+        for (max_distance, reverse) in [(None, false), (Some(900), false), (None, true)] {
+            let options = PairOptions {
+                max_distance,
+                reverse,
+                top_k: None,
+            };
+            let all = find_inverse_pairs(&torus, &map, &options);
+            for k in [1, 7, 100, all.len() + 5] {
+                let best = find_inverse_pairs(
+                    &torus,
+                    &map,
+                    &PairOptions {
+                        top_k: Some(k),
+                        ..options
+                    },
+                );
+                assert_eq!(best, all[..k.min(all.len())], "{:?} k={}", options, k);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_inverse_pairs_with_reverse() {
+        let torus = Torus::FULL;
+        let (first, second, third) = ids();
+        let map = HashMap::from([
+            (first, info([0, 0], [1 << 31, 1 << 31])),
+            (second, info([(1 << 31) + 1, 1 << 31], [u32::MAX, 0])),
+            // meets first's flee point, but first's follow point is far from third's flee point
+            (third, info([1 << 31, 1 << 31], [1 << 20, 0])),
+        ]);
+
+        let pairs = find_inverse_pairs(
+            &torus,
+            &map,
+            &PairOptions {
+                max_distance: Some(16),
+                reverse: true,
+                ..PairOptions::default()
+            },
+        );
+
+        assert_eq!(
+            pairs,
+            vec![InversePair {
+                a: first,
+                b: second,
+                distance: 1,
+                reverse_distance: Some(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_inverse_pairs_by_other_metrics() {
+        let torus = Torus::new([1024, 1024]).unwrap();
+        let (first, second, third) = ids();
+        let map = HashMap::from([
+            (first, info([0, 0], [900, 900])),
+            // 6 and 6 along the axes from first's follow point
+            (second, info([300, 300], [1018, 6])),
+            // 8 and 0 along the axes
+            (third, info([600, 600], [8, 0])),
+        ]);
+        let options = PairOptions {
+            max_distance: Some(10),
+            ..PairOptions::default()
+        };
+        let partners = |pairs: Vec<InversePair>| -> Vec<(Uuid, u128)> {
+            pairs.iter().map(|pair| (pair.b, pair.distance)).collect()
+        };
+
+        assert_eq!(
+            partners(find_inverse_pairs_by(&torus, &map, &Manhattan, &options)),
+            vec![(third, 8)]
+        );
+        assert_eq!(
+            partners(find_inverse_pairs_by(&torus, &map, &Chebyshev, &options)),
+            vec![(second, 6), (third, 8)]
+        );
+        assert!(find_inverse_pairs(&torus, &map, &options).is_empty());
+    }
+}
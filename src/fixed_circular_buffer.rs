@@ -1,8 +1,11 @@
 use std::collections::VecDeque;
+use std::ops::Index;
 
+// The newest item is at the front (index 0) and the oldest at the back; pushing onto a full buffer
+// evicts the oldest.
 pub struct FixedCircularBuffer<T> {
     buffer: VecDeque<T>,
-    pub capacity: usize,
+    capacity: usize,
 }
 
 impl<T> FixedCircularBuffer<T> {
@@ -13,27 +16,96 @@ impl<T> FixedCircularBuffer<T> {
         }
     }
 
-    pub fn push_front(&mut self, item: T) {
-        if self.capacity > 0 {
-            if self.buffer.len() == self.capacity {
-                if !self.buffer.is_empty() {
-                    let _ = self.buffer.pop_back(); // discard the oldest item
-                }
-            }
-            self.buffer.push_front(item);
+    // Returns the item evicted to make room, which is `item` itself when the capacity is zero.
+    pub fn push_front(&mut self, item: T) -> Option<T> {
+        if self.capacity == 0 {
+            return Some(item);
         }
+        let evicted = if self.is_full() {
+            self.buffer.pop_back()
+        } else {
+            None
+        };
+        self.buffer.push_front(item);
+        evicted
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.buffer.pop_front()
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.buffer.pop_back()
     }
 
     pub fn front(&self) -> Option<&T> {
         self.buffer.front()
     }
 
+    pub fn back(&self) -> Option<&T> {
+        self.buffer.back()
+    }
+
+    // Counting from the newest item at 0.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.buffer.get(index)
+    }
+
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
 
-    pub fn back(&self) -> Option<&T> {
-        self.buffer.back()
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.buffer.len() == self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    // Newest first.
+    pub fn iter(&self) -> std::collections::vec_deque::Iter<'_, T> {
+        self.buffer.iter()
+    }
+
+    // Keeps only the items `keep` returns true for, in their order.
+    pub fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        self.buffer.retain(keep);
+    }
+
+    pub fn contains(&self, item: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.buffer.contains(item)
+    }
+
+    // Shrinking evicts the oldest items past the new capacity and returns them, newest first.
+    pub fn resize(&mut self, capacity: usize) -> Vec<T> {
+        let evicted = if self.buffer.len() > capacity {
+            self.buffer.split_off(capacity).into()
+        } else {
+            Vec::new()
+        };
+        self.capacity = capacity;
+        self.buffer.shrink_to(capacity);
+        evicted
+    }
+}
+
+impl<T> Index<usize> for FixedCircularBuffer<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.buffer[index]
     }
 }
 
@@ -68,34 +140,38 @@ impl<'a, T> IntoIterator for &'a mut FixedCircularBuffer<T> {
 mod tests {
     use super::*;
 
+    fn contents<T: Copy>(buffer: &FixedCircularBuffer<T>) -> Vec<T> {
+        buffer.iter().copied().collect()
+    }
+
     #[test]
     fn test_push_front() {
         let mut buffer = FixedCircularBuffer::new(3);
 
         buffer.push_front(1);
-        assert_eq!(buffer.buffer, vec![1]);
+        assert_eq!(contents(&buffer), vec![1]);
 
         buffer.push_front(2);
-        assert_eq!(buffer.buffer, vec![2, 1]);
+        assert_eq!(contents(&buffer), vec![2, 1]);
 
         buffer.push_front(3);
-        assert_eq!(buffer.buffer, vec![3, 2, 1]);
+        assert_eq!(contents(&buffer), vec![3, 2, 1]);
 
         buffer.push_front(4);
-        assert_eq!(buffer.buffer, vec![4, 3, 2]);
+        assert_eq!(contents(&buffer), vec![4, 3, 2]);
 
         buffer.push_front(5);
-        assert_eq!(buffer.buffer, vec![5, 4, 3]);
+        assert_eq!(contents(&buffer), vec![5, 4, 3]);
 
         buffer.push_front(6);
-        assert_eq!(buffer.buffer, vec![6, 5, 4]);
+        assert_eq!(contents(&buffer), vec![6, 5, 4]);
     }
 
     #[test]
     fn test_push_front_with_zero_capacity() {
         let mut buffer = FixedCircularBuffer::new(0);
-        buffer.push_front(1); // should do nothing
-        assert_eq!(buffer.buffer, VecDeque::new());
+        assert_eq!(buffer.push_front(1), Some(1)); // rejected straight away
+        assert!(buffer.is_empty());
     }
 
     #[test]
@@ -103,10 +179,10 @@ mod tests {
         let mut buffer = FixedCircularBuffer::new(1);
 
         buffer.push_front(1);
-        assert_eq!(buffer.buffer, vec![1]);
+        assert_eq!(contents(&buffer), vec![1]);
 
         buffer.push_front(2);
-        assert_eq!(buffer.buffer, vec![2]);
+        assert_eq!(contents(&buffer), vec![2]);
     }
 
     #[test]
@@ -115,9 +191,9 @@ mod tests {
 
         for i in 1..=100 {
             buffer.push_front(i);
-            assert_eq!(buffer.buffer.len(), i);
+            assert_eq!(buffer.len(), i);
         }
-        assert_eq!(buffer.buffer, (1..=100).rev().collect::<Vec<_>>());
+        assert_eq!(contents(&buffer), (1..=100).rev().collect::<Vec<_>>());
     }
 
     #[test]
@@ -127,23 +203,23 @@ mod tests {
         buffer.push_front(1);
         buffer.push_front(2);
         buffer.push_front(3);
-        assert_eq!(buffer.buffer, vec![3, 2, 1]);
+        assert_eq!(contents(&buffer), vec![3, 2, 1]);
 
-        let popped = buffer.buffer.pop_back();
+        let popped = buffer.pop_back();
         assert_eq!(popped, Some(1));
-        assert_eq!(buffer.buffer, vec![3, 2]);
+        assert_eq!(contents(&buffer), vec![3, 2]);
 
-        let popped = buffer.buffer.pop_back();
+        let popped = buffer.pop_back();
         assert_eq!(popped, Some(2));
-        assert_eq!(buffer.buffer, vec![3]);
+        assert_eq!(contents(&buffer), vec![3]);
 
-        let popped = buffer.buffer.pop_back();
+        let popped = buffer.pop_back();
         assert_eq!(popped, Some(3));
-        assert_eq!(buffer.buffer, vec![]);
+        assert!(buffer.is_empty());
 
-        let popped = buffer.buffer.pop_back();
+        let popped = buffer.pop_back();
         assert_eq!(popped, None);
-        assert_eq!(buffer.buffer, vec![]);
+        assert!(buffer.is_empty());
     }
 
     #[test]
@@ -157,7 +233,7 @@ mod tests {
         let front = buffer.front();
         assert_eq!(front, Some(&3));
 
-        buffer.buffer.clear();
+        buffer.clear();
 
         let front = buffer.front();
         assert_eq!(front, None);
@@ -174,7 +250,7 @@ mod tests {
         let back = buffer.back();
         assert_eq!(back, Some(&1));
 
-        buffer.buffer.clear();
+        buffer.clear();
 
         let back = buffer.back();
         assert_eq!(back, None);
@@ -198,7 +274,7 @@ mod tests {
         buffer.push_front(4);
         assert_eq!(buffer.len(), 3);
 
-        buffer.buffer.clear();
+        buffer.clear();
         assert_eq!(buffer.len(), 0);
     }
 
@@ -216,4 +292,80 @@ mod tests {
         assert_eq!(iter.next(), Some(1));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_push_front_returns_evicted() {
+        let mut buffer = FixedCircularBuffer::new(2);
+
+        assert_eq!(buffer.push_front(1), None);
+        assert_eq!(buffer.push_front(2), None);
+        assert!(buffer.is_full());
+        assert_eq!(buffer.push_front(3), Some(1));
+        assert_eq!(buffer.push_front(4), Some(2));
+        assert_eq!(contents(&buffer), vec![4, 3]);
+    }
+
+    #[test]
+    fn test_pop_front_get_and_index() {
+        let mut buffer = FixedCircularBuffer::new(3);
+        for i in 1..=4 {
+            buffer.push_front(i);
+        }
+
+        assert_eq!(buffer.get(0), Some(&4));
+        assert_eq!(buffer[2], 2);
+        assert_eq!(buffer.get(3), None);
+
+        assert_eq!(buffer.pop_front(), Some(4));
+        assert_eq!(buffer[0], 3);
+        assert!(!buffer.is_full());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_range() {
+        let mut buffer = FixedCircularBuffer::new(3);
+        buffer.push_front(1);
+        let _ = buffer[1];
+    }
+
+    #[test]
+    fn test_retain_and_contains() {
+        let mut buffer = FixedCircularBuffer::new(5);
+        for i in 1..=5 {
+            buffer.push_front(i);
+        }
+
+        buffer.retain(|i| i % 2 == 1);
+        assert_eq!(contents(&buffer), vec![5, 3, 1]);
+        assert!(buffer.contains(&3));
+        assert!(!buffer.contains(&4));
+
+        // the freed slots fill up before anything is evicted again
+        assert_eq!(buffer.push_front(6), None);
+        assert_eq!(buffer.push_front(7), None);
+        assert_eq!(buffer.push_front(8), Some(1));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut buffer = FixedCircularBuffer::new(4);
+        for i in 1..=4 {
+            buffer.push_front(i);
+        }
+
+        // shrinking drops the oldest
+        assert_eq!(buffer.resize(2), vec![2, 1]);
+        assert_eq!(contents(&buffer), vec![4, 3]);
+        assert_eq!(buffer.capacity(), 2);
+        assert_eq!(buffer.push_front(5), Some(3));
+
+        // growing keeps everything and makes room
+        assert!(buffer.resize(3).is_empty());
+        assert_eq!(buffer.push_front(6), None);
+        assert_eq!(contents(&buffer), vec![6, 5, 4]);
+
+        assert_eq!(buffer.resize(0), vec![6, 5, 4]);
+        assert_eq!(buffer.push_front(7), Some(7));
+    }
 }
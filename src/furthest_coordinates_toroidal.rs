@@ -1,77 +1,116 @@
-use crate::toroidal_distance_squared::toroidal_distance_squared;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
 
-pub fn furthest_coordinates_toroidal(x: u32, y: u32) -> (u32, u32) {
-    const SIZE: u32 = u32::MAX;
-
-    let candidate_x_1 = x.wrapping_add(SIZE / 2);
-    let candidate_y_1 = y.wrapping_add(SIZE / 2);
-    let candidate_x_2 = candidate_x_1.wrapping_add(SIZE);
-    let candidate_y_2 = candidate_y_1.wrapping_add(SIZE);
-
-    let dist1 = toroidal_distance_squared(candidate_x_1, candidate_y_1, x, y);
-    let dist2 = toroidal_distance_squared(candidate_x_2, candidate_y_2, x, y);
-
-    if dist1 >= dist2 {
-        (candidate_x_1, candidate_y_1)
-    } else {
-        (candidate_x_2, candidate_y_2)
-    }
+// The antipode: half a lap along each axis. On odd sizes both neighbours of the exact half are
+// equally far, and the lower one is returned.
+pub fn furthest_coordinates_toroidal<const D: usize>(
+    torus: &Torus<D>,
+    point: &TorusPoint<D>,
+) -> TorusPoint<D> {
+    let half_laps = torus.sizes().map(|size| (size / 2) as i64);
+    torus.shift(point, &half_laps)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::toroidal_distance_squared::toroidal_distance_squared;
+
+    const QUARTER: u32 = 1 << 30;
+
+    fn furthest(x: u32, y: u32) -> (u32, u32) {
+        let point = furthest_coordinates_toroidal(&Torus::FULL, &TorusPoint::new([x, y]));
+        (point.x(), point.y())
+    }
 
     #[test]
     fn test_furthest_coordinates_toroidal_from_zero() {
-        let x = 0;
-        let y = 0;
-        let result = furthest_coordinates_toroidal(x, y);
-        assert_eq!(result, (std::u32::MAX / 2, std::u32::MAX / 2));
+        assert_eq!(furthest(0, 0), (QUARTER * 2, QUARTER * 2));
     }
 
     #[test]
     fn test_furthest_coordinates_toroidal_from_middle() {
-        let x = std::u32::MAX / 2;
-        let y = std::u32::MAX / 2;
-        let result = furthest_coordinates_toroidal(x, y);
-        // -1 off is close enough
-        assert_eq!(result, (std::u32::MAX - 1, std::u32::MAX - 1));
+        assert_eq!(furthest(QUARTER * 2, QUARTER * 2), (0, 0));
     }
 
     #[test]
     fn test_furthest_coordinates_toroidal_from_quarter() {
-        let x = std::u32::MAX / 4;
-        let y = std::u32::MAX / 4;
-        let result = furthest_coordinates_toroidal(x, y);
-        // +1 off is close enough
-        let three_quarters = (std::u32::MAX / 4) * 3 + 1;
-        assert_eq!(result, (three_quarters, three_quarters));
+        assert_eq!(furthest(QUARTER, QUARTER), (QUARTER * 3, QUARTER * 3));
     }
 
     #[test]
     fn test_furthest_coordinates_toroidal_from_three_quarters() {
-        let three_quarters = (std::u32::MAX / 4) * 3;
-        let result = furthest_coordinates_toroidal(three_quarters, three_quarters);
-        // -3 off is probably close enough
-        let one_quarter = std::u32::MAX / 4 - 3;
-        assert_eq!(result, (one_quarter, one_quarter));
+        assert_eq!(furthest(QUARTER * 3, QUARTER * 3), (QUARTER, QUARTER));
     }
 
     #[test]
     fn test_furthest_coordinates_toroidal_opposite_edges() {
-        let x = std::u32::MAX;
-        let y = 0;
-        let result = furthest_coordinates_toroidal(x, y);
-        // -1 is close enough
-        assert_eq!(result, (std::u32::MAX / 2 - 1, std::u32::MAX / 2));
+        assert_eq!(furthest(u32::MAX, 0), (QUARTER * 2 - 1, QUARTER * 2));
     }
 
     #[test]
     fn test_furthest_coordinates_toroidal_halfway() {
-        let x = std::u32::MAX;
-        let y = std::u32::MAX / 2;
-        let result = furthest_coordinates_toroidal(x, y);
-        assert_eq!(result, (std::u32::MAX / 2 - 1, std::u32::MAX - 1));
+        assert_eq!(furthest(u32::MAX, u32::MAX / 2), (u32::MAX / 2, u32::MAX));
+    }
+
+    #[test]
+    fn test_furthest_coordinates_toroidal_on_small_torus() {
+        let torus = Torus::new([1024, 1024]).unwrap();
+        assert_eq!(
+            furthest_coordinates_toroidal(&torus, &TorusPoint::ORIGIN),
+            TorusPoint::new([512, 512])
+        );
+        assert_eq!(
+            furthest_coordinates_toroidal(&torus, &TorusPoint::new([1000, 100])),
+            TorusPoint::new([488, 612])
+        );
+    }
+
+    #[test]
+    fn test_furthest_coordinates_toroidal_on_odd_torus_is_furthest() {
+        let torus = Torus::new([9, 4]).unwrap();
+        let point = TorusPoint::new([7, 1]);
+        let furthest = furthest_coordinates_toroidal(&torus, &point);
+        assert_eq!(furthest, TorusPoint::new([2, 3]));
+
+        for other_x in 0..9 {
+            for other_y in 0..4 {
+                let other = TorusPoint::new([other_x, other_y]);
+                assert!(
+                    toroidal_distance_squared(&torus, &point, &other)
+                        <= toroidal_distance_squared(&torus, &point, &furthest)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_furthest_coordinates_toroidal_is_exact_for_every_small_size() {
+        for width in 1..=12 {
+            for height in 1..=12 {
+                let torus = Torus::new([width, height]).unwrap();
+                let diameter = (width / 2).pow(2) as u128 + (height / 2).pow(2) as u128;
+                for x in 0..width as u32 {
+                    for y in 0..height as u32 {
+                        let point = TorusPoint::new([x, y]);
+                        let furthest = furthest_coordinates_toroidal(&torus, &point);
+                        assert_eq!(
+                            toroidal_distance_squared(&torus, &point, &furthest),
+                            diameter
+                        );
+                        assert_eq!(torus.wrap(&furthest), furthest);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_furthest_coordinates_toroidal_in_three_dimensions() {
+        let torus = Torus::new([1024, 1 << 32, 6]).unwrap();
+        assert_eq!(
+            furthest_coordinates_toroidal(&torus, &TorusPoint::new([1000, 1, 5])),
+            TorusPoint::new([488, (1 << 31) + 1, 2])
+        );
     }
 }
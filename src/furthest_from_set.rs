@@ -0,0 +1,196 @@
+use crate::toroidal_grid_index::ToroidalGridIndex;
+use crate::toroidal_metric::ToroidalMetric;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use uuid::Uuid;
+
+const INDEX_CELLS_PER_AXIS: u32 = 16;
+// how many lattice points are scored before refining; a torus with no more points than this is
+// searched exhaustively, so the answer there is exact
+const SAMPLE_BUDGET: u64 = 4096;
+// how many of the best scored candidates are refined
+const REFINED_CANDIDATES: usize = 8;
+
+// The point whose distance to the nearest of `points` is largest, with that distance: where a new
+// event lands furthest from every existing one rather than opposite their centroid. `None` when
+// `points` is empty.
+//
+// Scores the antipode of every point and a lattice spread evenly over the torus, then climbs from
+// the best few by halving steps along each axis. The lattice covers a small torus completely; on
+// a large one the result is a local optimum that is close to the true one but not guaranteed to be
+// it.
+pub fn furthest_from_set<const D: usize, M: ToroidalMetric<D>>(
+    torus: &Torus<D>,
+    metric: &M,
+    points: &[TorusPoint<D>],
+) -> Option<(TorusPoint<D>, u128)> {
+    match points {
+        [] => return None,
+        [point] => {
+            let furthest = metric.furthest(torus, point);
+            return Some((furthest, metric.distance(torus, point, &furthest)));
+        }
+        _ => {}
+    }
+
+    let mut index = ToroidalGridIndex::new(*torus, INDEX_CELLS_PER_AXIS);
+    for (i, point) in points.iter().enumerate() {
+        index.insert(Uuid::from_u128(i as u128), *point);
+    }
+    let score = |point: &TorusPoint<D>| {
+        index
+            .nearest_by(metric, point, 1)
+            .first()
+            .map_or(0, |(_, distance)| *distance)
+    };
+
+    let per_axis = lattice_points_per_axis(D);
+    let steps = torus.sizes().map(|size| size / per_axis.min(size));
+    let mut candidates: Vec<(u128, TorusPoint<D>)> = points
+        .iter()
+        .map(|point| metric.furthest(torus, point))
+        .chain(lattice(torus, per_axis))
+        .map(|point| (score(&point), point))
+        .collect();
+    // best first, and by coordinates among equals so the result does not depend on input order
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.coordinates.cmp(&b.1.coordinates)));
+    candidates.dedup_by(|a, b| a.1 == b.1);
+
+    candidates
+        .into_iter()
+        .take(REFINED_CANDIDATES)
+        .map(|(best, point)| climb(torus, &score, point, best, steps))
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.coordinates.cmp(&a.1.coordinates)))
+        .map(|(distance, point)| (point, distance))
+}
+
+// The largest g with g^dimensions within the sample budget.
+fn lattice_points_per_axis(dimensions: usize) -> u64 {
+    let mut per_axis = 1u64;
+    while (per_axis + 1)
+        .checked_pow(dimensions as u32)
+        .is_some_and(|total| total <= SAMPLE_BUDGET)
+    {
+        per_axis += 1;
+    }
+    per_axis
+}
+
+// `per_axis` evenly spaced points along each axis, or every coordinate of a shorter axis.
+fn lattice<const D: usize>(torus: &Torus<D>, per_axis: u64) -> Vec<TorusPoint<D>> {
+    let mut points = vec![TorusPoint::ORIGIN];
+    for (axis, &size) in torus.sizes().iter().enumerate() {
+        let count = per_axis.min(size);
+        points = points
+            .into_iter()
+            .flat_map(|point| {
+                (0..count).map(move |i| {
+                    let mut next = point;
+                    next[axis] = (i as u128 * size as u128 / count as u128) as u32;
+                    next
+                })
+            })
+            .collect();
+    }
+    points
+}
+
+// Moves along single axes while that improves the score, halving the step when nothing does.
+fn climb<const D: usize>(
+    torus: &Torus<D>,
+    score: &impl Fn(&TorusPoint<D>) -> u128,
+    mut point: TorusPoint<D>,
+    mut best: u128,
+    mut steps: [u64; D],
+) -> (u128, TorusPoint<D>) {
+    while steps.iter().any(|&step| step > 0) {
+        let mut improved = false;
+        for axis in 0..D {
+            for direction in [1i64, -1] {
+                let mut deltas = [0i64; D];
+                deltas[axis] = direction * steps[axis] as i64;
+                let next = torus.shift(&point, &deltas);
+                let next_score = score(&next);
+                if next_score > best {
+                    (point, best, improved) = (next, next_score, true);
+                }
+            }
+        }
+        if !improved {
+            steps = steps.map(|step| step / 2);
+        }
+    }
+    (best, point)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toroidal_metric::{Chebyshev, Euclidean, Manhattan};
+
+    fn brute_force<M: ToroidalMetric<2>>(torus: &Torus, metric: &M, points: &[TorusPoint]) -> u128 {
+        let mut best = 0;
+        for x in 0..torus.width() as u32 {
+            for y in 0..torus.height() as u32 {
+                let candidate = TorusPoint::new([x, y]);
+                let nearest = points
+                    .iter()
+                    .map(|point| metric.distance(torus, &candidate, point))
+                    .min()
+                    .unwrap();
+                best = best.max(nearest);
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn test_furthest_from_set_of_none_or_one() {
+        assert_eq!(furthest_from_set(&Torus::<2>::FULL, &Euclidean, &[]), None);
+
+        let torus = Torus::new([1000, 7]).unwrap();
+        assert_eq!(
+            furthest_from_set(&torus, &Euclidean, &[TorusPoint::new([900, 1])]),
+            Some((TorusPoint::new([400, 4]), 500 * 500 + 3 * 3))
+        );
+    }
+
+    #[test]
+    fn test_furthest_from_set_is_exact_on_small_torus() {
+        let torus = Torus::new([37, 23]).unwrap();
+        let points = [[0, 0], [18, 11], [30, 2], [5, 20], [36, 12]].map(TorusPoint::new);
+
+        let (point, distance) = furthest_from_set(&torus, &Euclidean, &points).unwrap();
+        assert_eq!(distance, brute_force(&torus, &Euclidean, &points));
+        assert_eq!(
+            points
+                .iter()
+                .map(|other| Euclidean.distance(&torus, &point, other))
+                .min(),
+            Some(distance)
+        );
+
+        assert_eq!(
+            furthest_from_set(&torus, &Manhattan, &points).unwrap().1,
+            brute_force(&torus, &Manhattan, &points)
+        );
+        assert_eq!(
+            furthest_from_set(&torus, &Chebyshev, &points).unwrap().1,
+            brute_force(&torus, &Chebyshev, &points)
+        );
+    }
+
+    #[test]
+    fn test_furthest_from_set_beats_any_antipode_on_full_torus() {
+        let (half, skew) = (1u32 << 31, 12_345);
+        let points = [[0, 0], [half, 0], [0, half], [half, half]]
+            .map(|[x, y]| TorusPoint::new([x + skew, y + skew]));
+
+        // every antipode lands on another point, while the centre of each square, off the
+        // lattice by the skew, is a quarter turn from its corners along both axes
+        let (point, distance) = furthest_from_set(&Torus::FULL, &Euclidean, &points).unwrap();
+        let optimum = 2u128 << 60;
+        assert!(distance > optimum / 100 * 99, "{:?} at {}", point, distance);
+        assert!(distance <= optimum);
+    }
+}
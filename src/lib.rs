@@ -0,0 +1,60 @@
+mod centroid_strategy;
+mod circular_mean_accumulator;
+mod concurrent_circular_buffer;
+mod durable_engine;
+mod engine;
+#[cfg(feature = "async")]
+mod engine_handle;
+mod error;
+mod event_json;
+mod eviction_policy;
+mod exponential_mean;
+mod find_inverse_pairs;
+mod fixed_circular_buffer;
+mod furthest_coordinates_toroidal;
+mod furthest_from_set;
+mod linear_weighted_mean;
+mod process_event;
+mod sharded_engine;
+mod snapshot;
+#[cfg(unix)]
+mod socket_client;
+mod toroidal_distance_squared;
+mod toroidal_grid_index;
+mod toroidal_mean_offset;
+mod toroidal_medoid;
+mod toroidal_metric;
+mod toroidal_rolling_flee_average;
+mod torus;
+mod torus_point;
+mod write_ahead_log;
+
+pub use centroid_strategy::CentroidStrategy;
+pub use circular_mean_accumulator::CircularMeanAccumulator;
+pub use concurrent_circular_buffer::ConcurrentCircularBuffer;
+pub use durable_engine::DurableEngine;
+pub use engine::Engine;
+#[cfg(feature = "async")]
+pub use engine_handle::{EngineHandle, HandleError, Placement};
+pub use error::Error;
+pub use eviction_policy::{EvictionPolicy, EvictionReason};
+pub use exponential_mean::{ExponentialMean, HalfLife};
+pub use find_inverse_pairs::{find_inverse_pairs, find_inverse_pairs_by, InversePair, PairOptions};
+pub use fixed_circular_buffer::FixedCircularBuffer;
+pub use furthest_coordinates_toroidal::furthest_coordinates_toroidal;
+pub use furthest_from_set::furthest_from_set;
+pub use linear_weighted_mean::LinearWeightedMean;
+pub use process_event::{process_event, Event, EventInfo};
+pub use sharded_engine::ShardedEngine;
+pub use snapshot::{SnapshotError, SNAPSHOT_VERSION};
+#[cfg(unix)]
+pub use socket_client::{ClientError, EngineStats, SocketClient};
+pub use toroidal_distance_squared::toroidal_distance_squared;
+pub use toroidal_grid_index::ToroidalGridIndex;
+pub use toroidal_mean_offset::toroidal_mean_offset;
+pub use toroidal_medoid::ToroidalMedoid;
+pub use toroidal_metric::{Chebyshev, Euclidean, Manhattan, ToroidalMetric, WeightedAxes};
+pub use toroidal_rolling_flee_average::toroidal_rolling_flee_average;
+pub use torus::Torus;
+pub use torus_point::TorusPoint;
+pub use write_ahead_log::{LogError, LogRecord, WriteAheadLog};
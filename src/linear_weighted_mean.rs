@@ -0,0 +1,147 @@
+use crate::centroid_strategy::{centroid_from_snapshot, window_after_push, CentroidStrategy};
+use crate::circular_mean_accumulator::CircularMeanAccumulator;
+use crate::error::Error;
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use crate::process_event::EventInfo;
+use crate::snapshot::SnapshotError;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// A weighted moving circular mean over the window: the newest flee point counts n times, the
+// next n - 1 times and so on down to once for the oldest, each also scaled by its event weight.
+// It is recomputed from the window on every admit, after any move has been applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinearWeightedMean<const D: usize = 2> {
+    torus: Torus<D>,
+    centroid: Option<TorusPoint<D>>,
+}
+
+impl<const D: usize> LinearWeightedMean<D> {
+    pub fn new(torus: Torus<D>) -> Self {
+        LinearWeightedMean {
+            torus,
+            centroid: None,
+        }
+    }
+}
+
+impl<const D: usize> CentroidStrategy<D> for LinearWeightedMean<D> {
+    const NAME: &'static str = "linear-weighted";
+
+    fn torus(&self) -> &Torus<D> {
+        &self.torus
+    }
+
+    fn admit(
+        &mut self,
+        event_map: &HashMap<Uuid, EventInfo<D>>,
+        event_buffer: &FixedCircularBuffer<Uuid>,
+        latest_uuid: &Uuid,
+    ) -> Result<(), Error> {
+        let window = window_after_push(event_map, event_buffer, latest_uuid)?;
+        let mut sums = CircularMeanAccumulator::with_torus(self.torus);
+        for (age, info) in window.iter().enumerate() {
+            let recency = u64::try_from(window.len() - age).map_err(|_| Error::Overflow)?;
+            let weight = recency
+                .checked_mul(info.weight as u64)
+                .ok_or(Error::Overflow)?;
+            sums.add_wide(&info.flee, weight)?;
+        }
+        self.centroid = sums.mean();
+        Ok(())
+    }
+
+    fn moved(&mut self, _: &EventInfo<D>, _: &EventInfo<D>, _: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn centroid(&self) -> Option<TorusPoint<D>> {
+        self.centroid
+    }
+
+    fn to_snapshot(&self) -> Value {
+        json!({ "centroid": self.centroid.map(|point| point.coordinates.to_vec()) })
+    }
+
+    fn from_snapshot(torus: Torus<D>, snapshot: &Value) -> Result<Self, SnapshotError> {
+        Ok(LinearWeightedMean {
+            torus,
+            centroid: centroid_from_snapshot(snapshot)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flee_at(x: u32) -> EventInfo {
+        EventInfo::new(TorusPoint::ORIGIN, TorusPoint::new([x, 0]))
+    }
+
+    #[test]
+    fn test_linear_weighted_mean_favours_recent_points() {
+        let torus = Torus::new([360, 360]).unwrap();
+        let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+        let map = HashMap::from([(old, flee_at(0)), (new, flee_at(90))]);
+        let mut buffer = FixedCircularBuffer::new(2);
+        buffer.push_front(old);
+
+        let mut mean = LinearWeightedMean::new(torus);
+        mean.admit(&map, &buffer, &new).unwrap();
+
+        // 90 counts twice and 0 once: atan2(2, 1) is 63.4 degrees
+        assert_eq!(mean.centroid(), Some(TorusPoint::new([63, 0])));
+    }
+
+    #[test]
+    fn test_linear_weighted_mean_drops_evicted_and_scales_by_event_weight() {
+        let torus = Torus::new([360, 360]).unwrap();
+        let (evicted, kept, latest) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let map = HashMap::from([
+            (evicted, flee_at(180)),
+            (
+                kept,
+                EventInfo {
+                    weight: 2,
+                    ..flee_at(0)
+                },
+            ),
+            (latest, flee_at(90)),
+        ]);
+        let mut buffer = FixedCircularBuffer::new(2);
+        buffer.push_front(evicted);
+        buffer.push_front(kept);
+
+        let mut mean = LinearWeightedMean::new(torus);
+        mean.admit(&map, &buffer, &latest).unwrap();
+
+        // 90 counts 2 * 1 and 0 counts 1 * 2, so they meet half way
+        assert_eq!(mean.centroid(), Some(TorusPoint::new([45, 0])));
+        assert_eq!(
+            LinearWeightedMean::from_snapshot(torus, &mean.to_snapshot()).unwrap(),
+            mean
+        );
+    }
+
+    #[test]
+    fn test_linear_weighted_mean_takes_heavy_events() {
+        let torus = Torus::new([360, 360]).unwrap();
+        let heavy = |x| EventInfo {
+            weight: u32::MAX,
+            ..flee_at(x)
+        };
+        let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+        let map = HashMap::from([(old, heavy(0)), (new, heavy(90))]);
+        let mut buffer = FixedCircularBuffer::new(2);
+        buffer.push_front(old);
+
+        // twice u32::MAX no longer fits a u32, but the newest still counts double
+        let mut mean = LinearWeightedMean::new(torus);
+        mean.admit(&map, &buffer, &new).unwrap();
+        assert_eq!(mean.centroid(), Some(TorusPoint::new([63, 0])));
+    }
+}
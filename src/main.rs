@@ -1,9 +1,427 @@
-mod fixed_circular_buffer;
-mod furthest_coordinates_toroidal;
-mod process_event;
-mod toroidal_distance_squared;
-mod toroidal_rolling_flee_average;
+use inverse_pairs::{
+    CentroidStrategy, Engine, Event, EventInfo, ExponentialMean, HalfLife, LinearWeightedMean,
+    ToroidalMedoid, Torus,
+};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process;
+use uuid::Uuid;
+
+const USAGE: &str = "usage: inverse-pairs [--capacity N] [--format jsonl|csv] [--torus WxH]
+                     [--strategy windowed|exponential|linear-weighted|medoid]
+                     [--half-life EVENTS] [FILE]
+
+Reads one event per line from FILE (or stdin when FILE is omitted or `-`), either as a bare UUID
+or as a JSON object with an \"id\" field and optional \"timestamp\", \"weight\" and \"payload\"
+fields, and writes each event's placement. New events are placed opposite the circular mean of the
+last N flee points by default; --strategy picks another centroid: an exponentially decayed mean
+(--half-life, which implies it), a mean weighted by recency, or the medoid of the window.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    JsonLines,
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Strategy {
+    Windowed,
+    Exponential,
+    LinearWeighted,
+    Medoid,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    capacity: usize,
+    format: OutputFormat,
+    torus: Torus,
+    strategy: Strategy,
+    half_life: Option<f64>,
+    input: Option<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        capacity: 64,
+        format: OutputFormat::JsonLines,
+        torus: Torus::FULL,
+        strategy: Strategy::Windowed,
+        half_life: None,
+        input: None,
+    };
+    let mut strategy = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--capacity" => {
+                let value = args.next().ok_or("--capacity needs a value")?;
+                options.capacity = match value.parse() {
+                    Ok(capacity) if capacity > 0 => capacity,
+                    _ => return Err(format!("invalid capacity: {}", value)),
+                };
+            }
+            "-f" | "--format" => {
+                let value = args.next().ok_or("--format needs a value")?;
+                options.format = match value.as_str() {
+                    "jsonl" | "json" => OutputFormat::JsonLines,
+                    "csv" => OutputFormat::Csv,
+                    _ => return Err(format!("unknown format: {}", value)),
+                };
+            }
+            "-t" | "--torus" => {
+                let value = args.next().ok_or("--torus needs a value")?;
+                options.torus = parse_torus(&value)?;
+            }
+            "-s" | "--strategy" => {
+                let value = args.next().ok_or("--strategy needs a value")?;
+                strategy = Some(match value.as_str() {
+                    "windowed" => Strategy::Windowed,
+                    "exponential" => Strategy::Exponential,
+                    "linear-weighted" => Strategy::LinearWeighted,
+                    "medoid" => Strategy::Medoid,
+                    _ => return Err(format!("unknown strategy: {}", value)),
+                });
+            }
+            "-l" | "--half-life" => {
+                let value = args.next().ok_or("--half-life needs a value")?;
+                options.half_life = match value.parse::<f64>() {
+                    Ok(events) if events > 0.0 && events.is_finite() => Some(events),
+                    _ => return Err(format!("invalid half-life: {}", value)),
+                };
+            }
+            "-" => options.input = None,
+            _ if arg.starts_with('-') => return Err(format!("unknown flag: {}", arg)),
+            _ if options.input.is_some() => return Err("only one input file is allowed".into()),
+            _ => options.input = Some(arg),
+        }
+    }
+
+    options.strategy = match (strategy, options.half_life) {
+        (None, None) => Strategy::Windowed,
+        (None, Some(_)) => Strategy::Exponential,
+        (Some(Strategy::Exponential), None) => {
+            return Err("--strategy exponential needs --half-life".into())
+        }
+        (Some(Strategy::Exponential), Some(_)) => Strategy::Exponential,
+        (Some(_), Some(_)) => return Err("--half-life only applies to exponential".into()),
+        (Some(chosen), None) => chosen,
+    };
+
+    Ok(options)
+}
+
+fn parse_torus(value: &str) -> Result<Torus, String> {
+    let invalid = || format!("invalid torus: {}", value);
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width = width.parse().map_err(|_| invalid())?;
+    let height = height.parse().map_err(|_| invalid())?;
+    Torus::new([width, height]).map_err(|error| error.to_string())
+}
+
+// Blank lines are skipped, so `Ok(None)` means there was nothing to ingest.
+fn parse_event_line(line: &str) -> Result<Option<Event>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.starts_with('{') {
+        return parse_id(line).map(|id| Some(Event::new(id)));
+    }
+
+    let value: serde_json::Value =
+        serde_json::from_str(line).map_err(|error| format!("invalid JSON: {}", error))?;
+    Event::from_json(&value).map(Some)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, String> {
+    Uuid::parse_str(id).map_err(|error| format!("invalid id {}: {}", id, error))
+}
+
+fn format_placement(id: &Uuid, info: &EventInfo, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::JsonLines => serde_json::json!({
+            "id": id.to_string(),
+            "follow_x": info.follow.x(),
+            "follow_y": info.follow.y(),
+            "flee_x": info.flee.x(),
+            "flee_y": info.flee.y(),
+        })
+        .to_string(),
+        OutputFormat::Csv => format!(
+            "{},{},{},{},{}",
+            id,
+            info.follow.x(),
+            info.follow.y(),
+            info.flee.x(),
+            info.flee.y()
+        ),
+    }
+}
+
+fn run(input: impl BufRead, output: impl Write, options: &Options) -> Result<(), String> {
+    let (capacity, torus) = (options.capacity, options.torus);
+    match options.strategy {
+        Strategy::Windowed => replay(Engine::with_torus(capacity, torus), input, output, options),
+        Strategy::Exponential => {
+            let events = options.half_life.ok_or("--half-life is missing")?;
+            let centroid = ExponentialMean::new(torus, HalfLife::Events(events))
+                .map_err(|error| error.to_string())?;
+            replay(
+                Engine::with_strategy(capacity, centroid),
+                input,
+                output,
+                options,
+            )
+        }
+        Strategy::LinearWeighted => replay(
+            Engine::with_strategy(capacity, LinearWeightedMean::new(torus)),
+            input,
+            output,
+            options,
+        ),
+        Strategy::Medoid => replay(
+            Engine::with_strategy(capacity, ToroidalMedoid::new(torus)),
+            input,
+            output,
+            options,
+        ),
+    }
+}
+
+fn replay<S: CentroidStrategy<2>>(
+    mut engine: Engine<2, S>,
+    input: impl BufRead,
+    mut output: impl Write,
+    options: &Options,
+) -> Result<(), String> {
+    if options.format == OutputFormat::Csv {
+        writeln!(output, "id,follow_x,follow_y,flee_x,flee_y").map_err(|e| e.to_string())?;
+    }
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        let event = match parse_event_line(&line) {
+            Ok(Some(event)) => event,
+            Ok(None) => continue,
+            Err(error) => return Err(format!("line {}: {}", index + 1, error)),
+        };
+
+        let info = engine
+            .ingest(&event)
+            .map_err(|error| format!("line {}: {}", index + 1, error))?;
+        writeln!(
+            output,
+            "{}",
+            format_placement(&event.id, &info, options.format)
+        )
+        .map_err(|error| error.to_string())?;
+    }
+
+    output.flush().map_err(|error| error.to_string())
+}
 
 fn main() {
-    println!("Hello, world!");
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let output = BufWriter::new(io::stdout().lock());
+    let result = match &options.input {
+        Some(path) => match File::open(path) {
+            Ok(file) => run(BufReader::new(file), output, &options),
+            Err(error) => Err(format!("{}: {}", path, error)),
+        },
+        None => run(io::stdin().lock(), output, &options),
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args_defaults() {
+        assert_eq!(
+            parse_args(args(&[])),
+            Ok(Options {
+                capacity: 64,
+                format: OutputFormat::JsonLines,
+                torus: Torus::FULL,
+                strategy: Strategy::Windowed,
+                half_life: None,
+                input: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_args_flags_and_file() {
+        assert_eq!(
+            parse_args(args(&[
+                "--capacity",
+                "8",
+                "--format",
+                "csv",
+                "--torus",
+                "1024x512",
+                "--half-life",
+                "2.5",
+                "events.log"
+            ])),
+            Ok(Options {
+                capacity: 8,
+                format: OutputFormat::Csv,
+                torus: Torus::new([1024, 512]).unwrap(),
+                strategy: Strategy::Exponential,
+                half_life: Some(2.5),
+                input: Some("events.log".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_args_rejects_bad_values() {
+        assert!(parse_args(args(&["--capacity", "many"])).is_err());
+        assert!(parse_args(args(&["--capacity", "0"])).is_err());
+        assert!(parse_args(args(&["--format", "xml"])).is_err());
+        assert!(parse_args(args(&["--verbose"])).is_err());
+        assert!(parse_args(args(&["a.log", "b.log"])).is_err());
+        assert!(parse_args(args(&["--torus", "1024"])).is_err());
+        assert!(parse_args(args(&["--torus", "0x10"])).is_err());
+        assert!(parse_args(args(&["--half-life", "0"])).is_err());
+        assert!(parse_args(args(&["--strategy", "median"])).is_err());
+        assert!(parse_args(args(&["--strategy", "exponential"])).is_err());
+        assert!(parse_args(args(&["--strategy", "medoid", "--half-life", "2"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_event_line() {
+        let id = Uuid::parse_str("fa84077a-7a27-48cf-b6f4-0becc82b09ac").unwrap();
+        assert_eq!(
+            parse_event_line("fa84077a-7a27-48cf-b6f4-0becc82b09ac\n"),
+            Ok(Some(Event::new(id)))
+        );
+        assert_eq!(
+            parse_event_line(r#"{"id": "fa84077a-7a27-48cf-b6f4-0becc82b09ac", "kind": "x"}"#),
+            Ok(Some(Event::new(id)))
+        );
+        assert_eq!(
+            parse_event_line(
+                r#"{"id": "fa84077a-7a27-48cf-b6f4-0becc82b09ac", "timestamp": 17, "weight": 3, "payload": "abc"}"#
+            ),
+            Ok(Some(
+                Event::new(id)
+                    .with_timestamp(17)
+                    .with_weight(3)
+                    .with_payload("abc")
+            ))
+        );
+        assert!(parse_event_line(
+            r#"{"id": "fa84077a-7a27-48cf-b6f4-0becc82b09ac", "weight": -1}"#
+        )
+        .is_err());
+        assert_eq!(parse_event_line("   "), Ok(None));
+        assert!(parse_event_line("not-a-uuid").is_err());
+        assert!(parse_event_line(r#"{"uuid": "fa84077a-7a27-48cf-b6f4-0becc82b09ac"}"#).is_err());
+    }
+
+    #[test]
+    fn test_run_writes_json_lines() {
+        let input = "fa84077a-7a27-48cf-b6f4-0becc82b09ac\n\n{\"id\":\"96d9a909-87ce-4b94-a877-462fdc56831d\"}\n";
+        let options = parse_args(args(&[])).unwrap();
+        let mut output = Vec::new();
+
+        run(input.as_bytes(), &mut output, &options).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["id"], "fa84077a-7a27-48cf-b6f4-0becc82b09ac");
+        assert_eq!(lines[0]["follow_x"], 1u32 << 31);
+        assert_eq!(lines[1]["id"], "96d9a909-87ce-4b94-a877-462fdc56831d");
+    }
+
+    #[test]
+    fn test_run_writes_csv() {
+        let input = "fa84077a-7a27-48cf-b6f4-0becc82b09ac\n";
+        let options = parse_args(args(&["--format", "csv"])).unwrap();
+        let mut output = Vec::new();
+
+        run(input.as_bytes(), &mut output, &options).unwrap();
+
+        let half = 1u32 << 31;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "id,follow_x,follow_y,flee_x,flee_y\nfa84077a-7a27-48cf-b6f4-0becc82b09ac,{},{},{},{}\n",
+                half, half, half, half
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_with_half_life() {
+        let input = "fa84077a-7a27-48cf-b6f4-0becc82b09ac\n96d9a909-87ce-4b94-a877-462fdc56831d\n";
+        let options = parse_args(args(&["--half-life", "1", "--format", "csv"])).unwrap();
+        let mut output = Vec::new();
+
+        run(input.as_bytes(), &mut output, &options).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 3);
+        assert!(output.ends_with("96d9a909-87ce-4b94-a877-462fdc56831d,0,0,0,0\n"));
+    }
+
+    #[test]
+    fn test_run_with_each_strategy() {
+        let input = "fa84077a-7a27-48cf-b6f4-0becc82b09ac\n96d9a909-87ce-4b94-a877-462fdc56831d\n";
+        for strategy in ["windowed", "linear-weighted", "medoid"] {
+            let options = parse_args(args(&["--strategy", strategy, "--format", "csv"])).unwrap();
+            let mut output = Vec::new();
+
+            run(input.as_bytes(), &mut output, &options).unwrap();
+
+            // the second event flees from the first, which fled to the middle
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.ends_with("96d9a909-87ce-4b94-a877-462fdc56831d,0,0,0,0\n"));
+        }
+    }
+
+    #[test]
+    fn test_run_reports_line_of_bad_input() {
+        let input = "fa84077a-7a27-48cf-b6f4-0becc82b09ac\nnope\n";
+        // engine errors carry the line too; the flags no longer allow this one
+        let options = Options {
+            capacity: 0,
+            ..parse_args(args(&[])).unwrap()
+        };
+
+        let result = run(input.as_bytes(), Vec::new(), &options);
+        assert_eq!(
+            result,
+            Err("line 1: the event buffer has zero capacity".to_string())
+        );
+
+        let options = parse_args(args(&[])).unwrap();
+        let result = run(input.as_bytes(), Vec::new(), &options);
+        assert!(result.unwrap_err().starts_with("line 2: invalid id nope"));
+    }
 }
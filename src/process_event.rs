@@ -1,77 +1,173 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::error::Error;
 use crate::fixed_circular_buffer::FixedCircularBuffer;
 use crate::furthest_coordinates_toroidal::furthest_coordinates_toroidal;
-use crate::toroidal_rolling_flee_average::toroidal_rolling_flee_average;
+use crate::toroidal_mean_offset::toroidal_mean_offset;
+use crate::torus_point::TorusPoint;
 use std::collections::HashMap;
 use uuid::Uuid;
 
-struct Event {
-    id: Uuid,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub id: Uuid,
+    // milliseconds since the Unix epoch, when the caller knows it
+    pub timestamp: Option<u64>,
+    // how strongly this event pulls the rolling flee average; 1 unless set, and never 0
+    pub weight: u32,
+    // opaque to the engine and carried through untouched
+    pub payload: Vec<u8>,
 }
 
-#[derive(PartialEq, Debug)]
-pub struct EventInfo {
-    pub follow_x: u32,
-    pub follow_y: u32,
-    pub flee_x: u32,
-    pub flee_y: u32,
+impl Event {
+    pub fn new(id: Uuid) -> Self {
+        Event {
+            id,
+            timestamp: None,
+            weight: 1,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    // A zero weight is rejected when the event is processed.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
 }
 
-// TODO: fix tests since in the middle of swapping out average with rolling toroidal average still being written
-fn process_event(
+impl From<Uuid> for Event {
+    fn from(id: Uuid) -> Self {
+        Event::new(id)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EventInfo<const D: usize = 2> {
+    pub follow: TorusPoint<D>,
+    pub flee: TorusPoint<D>,
+    // the weight and timestamp of the latest event with this id
+    pub weight: u32,
+    pub timestamp: Option<u64>,
+}
+
+impl<const D: usize> EventInfo<D> {
+    pub fn new(follow: TorusPoint<D>, flee: TorusPoint<D>) -> Self {
+        EventInfo {
+            follow,
+            flee,
+            weight: 1,
+            timestamp: None,
+        }
+    }
+}
+
+// How far a repeated event moves towards (or away from) the recent centroid, as a divisor of the offset.
+const SHIFT_DIVISOR: i64 = 2;
+
+// `centroid` must have seen every push onto `buffer` and every move of a placement in it; it is
+// kept that way here. With a `CircularMeanAccumulator` that means it holds the flee points of
+// exactly the ids in `buffer`, each counted with its id's current weight. Returns the id pushed
+// out of the back of the full window, if any; its placement stays in `map`. On error `buffer`,
+// `map` and `centroid` are left as they were.
+pub fn process_event<const D: usize, S: CentroidStrategy<D>>(
     event: &Event,
     buffer: &mut FixedCircularBuffer<Uuid>,
-    map: &mut HashMap<Uuid, EventInfo>,
-    previous_flee_average: &Option<(u32, u32)>,
-) -> Result<(), Uuid> {
-    let sum_flee_coordinates =
-        toroidal_rolling_flee_average(map, buffer, previous_flee_average).unwrap();
-
-    if map.contains_key(&event.id) {
-        // TODO: shift incoming event follow towards flees
-        // TODO: shift incoming event flee away from follows
-        println!("Event is in hashmap!");
-    } else {
-        let (flee_anti_x, flee_anti_y) =
-            furthest_coordinates_toroidal(sum_flee_coordinates.0, sum_flee_coordinates.1);
+    map: &mut HashMap<Uuid, EventInfo<D>>,
+    centroid: &mut S,
+) -> Result<Option<Uuid>, Error> {
+    if buffer.capacity() == 0 {
+        return Err(Error::ZeroCapacity);
+    }
+    if event.weight == 0 {
+        return Err(Error::ZeroWeight(event.id));
+    }
+    // the centroid already measures angles on this torus, so every placement uses it too
+    let torus = *centroid.torus();
 
-        // TODO: fix broken test because you can't just sum and then take the average in toroidal space
-        println!(
-            "sums {:?} {:?}",
-            sum_flee_coordinates.0, sum_flee_coordinates.1,
-        );
-        println!("opposites {:?} {:?}", flee_anti_x, flee_anti_y);
+    if let Some(old_info) = map.get(&event.id).copied() {
+        let mut recent_flees = Vec::with_capacity(buffer.len());
+        let mut recent_follows = Vec::with_capacity(buffer.len());
+        let mut occurrences = 0;
+        for id in buffer.into_iter() {
+            if *id == event.id {
+                occurrences += 1;
+            } else {
+                let recent = map.get(id).ok_or(Error::MissingId(*id))?;
+                recent_flees.push(recent.flee);
+                recent_follows.push(recent.follow);
+            }
+        }
+
+        let mut info = old_info;
+        if let Some(offset) = toroidal_mean_offset(&torus, &info.follow, &recent_flees) {
+            info.follow = torus.shift(&info.follow, &offset.map(|delta| delta / SHIFT_DIVISOR));
+        }
+        if let Some(offset) = toroidal_mean_offset(&torus, &info.flee, &recent_follows) {
+            info.flee = torus.shift(&info.flee, &offset.map(|delta| -delta / SHIFT_DIVISOR));
+        }
+
+        info.weight = event.weight;
+        info.timestamp = event.timestamp;
+
+        // every copy of this id still in the window now flees from the new point, at the new weight;
+        // if the admit after it fails, the strategy is put back as it was before the move
+        let saved = centroid.clone();
+        centroid.moved(&old_info, &info, occurrences)?;
+        map.insert(event.id, info);
+        if let Err(error) = centroid.admit(map, buffer, &event.id) {
+            map.insert(event.id, old_info);
+            *centroid = saved;
+            return Err(error);
+        }
+    } else {
+        // an empty window has nothing to flee from yet, so measure from the origin
+        let flee_mean = centroid.centroid().unwrap_or(TorusPoint::ORIGIN);
+        let flee_anti = furthest_coordinates_toroidal(&torus, &flee_mean);
 
         // The idea is to place initial points far from each other and continue some consistent rule.
         let event_info = EventInfo {
-            follow_x: flee_anti_x,
-            follow_y: flee_anti_y,
-            flee_x: 0,
-            flee_y: 0,
+            follow: flee_anti,
+            flee: flee_anti,
+            weight: event.weight,
+            timestamp: event.timestamp,
         };
         map.insert(event.id, event_info);
+        if let Err(error) = centroid.admit(map, buffer, &event.id) {
+            map.remove(&event.id);
+            return Err(error);
+        }
     }
 
-    buffer.push_front(event.id);
-    return Ok(());
+    Ok(buffer.push_front(event.id))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::circular_mean_accumulator::CircularMeanAccumulator;
 
     #[test]
     fn test_process_event_inserts_first_item() {
         let mut buffer = FixedCircularBuffer::<Uuid>::new(64);
         let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
 
-        let event = Event { id: Uuid::new_v4() };
-        let event_info = EventInfo {
-            follow_x: u32::MAX / 2,
-            follow_y: u32::MAX / 2,
-            flee_x: u32::MAX / 2,
-            flee_y: u32::MAX / 2,
-        };
-        process_event(&event, &mut buffer, &mut map, &Some((0, 0)));
+        let event = Event::new(Uuid::new_v4());
+        let event_info = EventInfo::new(
+            TorusPoint::new([1 << 31, 1 << 31]),
+            TorusPoint::new([1 << 31, 1 << 31]),
+        );
+        process_event(&event, &mut buffer, &mut map, &mut flee_sums).unwrap();
 
         assert_eq!(buffer.front(), Some(&event.id));
         assert_eq!(buffer.len(), 1);
@@ -84,27 +180,17 @@ mod tests {
     fn test_process_event_inserts_second_item() {
         let mut buffer = FixedCircularBuffer::new(64);
         let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
 
-        let event1 = Event {
-            id: Uuid::parse_str("fa84077a-7a27-48cf-b6f4-0becc82b09ac").unwrap(),
-        };
-        let event2 = Event {
-            id: Uuid::parse_str("96d9a909-87ce-4b94-a877-462fdc56831d").unwrap(),
-        };
-        let event1_info = EventInfo {
-            follow_x: u32::MAX / 2,
-            follow_y: u32::MAX / 2,
-            flee_x: u32::MAX / 2,
-            flee_y: u32::MAX / 2,
-        };
-        let event2_info = EventInfo {
-            follow_x: u32::MAX - 1,
-            follow_y: u32::MAX - 1,
-            flee_x: u32::MAX - 1,
-            flee_y: u32::MAX - 1,
-        };
-        process_event(&event1, &mut buffer, &mut map, &Some((0, 0)));
-        process_event(&event2, &mut buffer, &mut map, &Some((0, 0)));
+        let event1 = Event::new(Uuid::parse_str("fa84077a-7a27-48cf-b6f4-0becc82b09ac").unwrap());
+        let event2 = Event::new(Uuid::parse_str("96d9a909-87ce-4b94-a877-462fdc56831d").unwrap());
+        let event1_info = EventInfo::new(
+            TorusPoint::new([1 << 31, 1 << 31]),
+            TorusPoint::new([1 << 31, 1 << 31]),
+        );
+        let event2_info = EventInfo::new(TorusPoint::new([0, 0]), TorusPoint::new([0, 0]));
+        process_event(&event1, &mut buffer, &mut map, &mut flee_sums).unwrap();
+        process_event(&event2, &mut buffer, &mut map, &mut flee_sums).unwrap();
 
         assert_eq!(buffer.len(), 2);
         assert_eq!(
@@ -133,14 +219,13 @@ mod tests {
     fn test_process_event_adds_same_id_to_buffer_twice() {
         let mut buffer = FixedCircularBuffer::new(3); // set buffer length to 2
         let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
 
-        let event1 = Event {
-            id: Uuid::parse_str("fa84077a-7a27-48cf-b6f4-0becc82b09ac").unwrap(),
-        };
-        process_event(&event1, &mut buffer, &mut map, &Some((0, 0))).unwrap();
+        let event1 = Event::new(Uuid::parse_str("fa84077a-7a27-48cf-b6f4-0becc82b09ac").unwrap());
+        process_event(&event1, &mut buffer, &mut map, &mut flee_sums).unwrap();
 
         // check if the entry is updated correctly by calling process_event again
-        process_event(&event1, &mut buffer, &mut map, &Some((0, 0))).unwrap();
+        process_event(&event1, &mut buffer, &mut map, &mut flee_sums).unwrap();
 
         // check that the buffer still inserts the id
         assert_eq!(
@@ -159,6 +244,54 @@ mod tests {
         assert_eq!(map.len(), 1);
     }
 
+    #[test]
+    fn test_process_event_shifts_repeated_event() {
+        let id_1 = Uuid::parse_str("f4ecfb47-3f6f-4f11-b5bf-67146c3afcfd").unwrap();
+        let id_2 = Uuid::parse_str("13b94a73-5a1b-407a-98a4-26d41ddfc9e5").unwrap();
+        let mut buffer = FixedCircularBuffer::new(4);
+        buffer.push_front(id_1);
+        buffer.push_front(id_2);
+
+        let mut map = HashMap::new();
+        map.insert(
+            id_1,
+            EventInfo::new(
+                TorusPoint::new([100, u32::MAX - 99]),
+                TorusPoint::new([1000, 1000]),
+            ),
+        );
+        // the recent flee point sits across the seam from the follow point
+        map.insert(
+            id_2,
+            EventInfo::new(
+                TorusPoint::new([1200, 800]),
+                TorusPoint::new([u32::MAX - 99, 100]),
+            ),
+        );
+
+        let mut flee_sums = CircularMeanAccumulator::new();
+        flee_sums.add(&TorusPoint::new([1000, 1000])).unwrap();
+        flee_sums
+            .add(&TorusPoint::new([u32::MAX - 99, 100]))
+            .unwrap();
+
+        let event_1 = Event::new(id_1);
+        process_event(&event_1, &mut buffer, &mut map, &mut flee_sums).unwrap();
+
+        // follow moved halfway towards id_2's flee point, the short way around
+        let info = map.get(&id_1).unwrap();
+        assert_eq!(info.follow.x(), 0);
+        assert_eq!(info.follow.y(), 0);
+
+        // flee moved away from id_2's follow point by half the offset
+        assert_eq!(info.flee.x(), 900);
+        assert_eq!(info.flee.y(), 1100);
+
+        assert_eq!(buffer.front(), Some(&id_1));
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(map.len(), 2);
+    }
+
     #[test]
     fn test_process_event_average_toroidal_point() {
         // Create a buffer with two events already in it
@@ -173,28 +306,30 @@ mod tests {
         let eigth = u32::MAX / 8;
         map.insert(
             id_1,
-            EventInfo {
-                follow_x: eigth,
-                follow_y: eigth * 3,
-                flee_x: eigth * 5,
-                flee_y: eigth,
-            },
+            EventInfo::new(
+                TorusPoint::new([eigth, eigth * 3]),
+                TorusPoint::new([eigth * 5, eigth]),
+            ),
         );
         map.insert(
             id_2,
-            EventInfo {
-                follow_x: u32::MAX - eigth,
-                follow_y: eigth * 5,
-                flee_x: eigth * 3,
-                flee_y: u32::MAX - eigth,
-            },
+            EventInfo::new(
+                TorusPoint::new([u32::MAX - eigth, eigth * 5]),
+                TorusPoint::new([eigth * 3, u32::MAX - eigth]),
+            ),
         );
+        let mut flee_sums = CircularMeanAccumulator::new();
+        flee_sums.add(&TorusPoint::new([eigth * 5, eigth])).unwrap();
+        flee_sums
+            .add(&TorusPoint::new([eigth * 3, u32::MAX - eigth]))
+            .unwrap();
+
         let id_3 = Uuid::parse_str("95893064-fbf9-41ec-b5d7-632bc76bbe9a").unwrap();
-        let event_3 = Event { id: id_3 };
+        let event_3 = Event::new(id_3);
 
         // Call the process_event function to add the third event
-        let result = process_event(&event_3, &mut buffer, &mut map, &Some((0, 0)));
-        assert!(result.is_ok());
+        let result = process_event(&event_3, &mut buffer, &mut map, &mut flee_sums);
+        assert_eq!(result, Ok(None));
 
         // Check that the third event is now in the buffer
         let buffer_contents: Vec<Uuid> = buffer.into_iter().collect();
@@ -203,11 +338,124 @@ mod tests {
         assert_eq!(buffer_contents[1], id_2);
         assert_eq!(buffer_contents[2], id_1);
 
-        // Check that the follow and flee points for the third event have been averaged
+        // The flee points average to half way along x and to the seam along y, so the third event
+        // is placed opposite that centroid
         let event3_info = map.get(&id_3).unwrap();
-        assert_eq!(event3_info.follow_x, u32::MAX);
-        assert_eq!(event3_info.flee_x, u32::MAX / 2);
-        assert_eq!(event3_info.follow_y, u32::MAX / 2);
-        assert_eq!(event3_info.flee_y, u32::MAX - 4);
+        assert_eq!(event3_info.follow.x(), u32::MAX - 3);
+        assert_eq!(event3_info.flee.x(), u32::MAX - 3);
+        assert_eq!(event3_info.follow.y(), 1 << 31);
+        assert_eq!(event3_info.flee.y(), 1 << 31);
+        assert_eq!(flee_sums.count, 3);
+    }
+
+    #[test]
+    fn test_process_event_rejects_zero_weight() {
+        let mut buffer = FixedCircularBuffer::new(4);
+        let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
+        let weightless = Event::new(Uuid::new_v4()).with_weight(0);
+
+        // a window of only weightless events would have a mean of atan2(0, 0)
+        assert_eq!(
+            process_event(&weightless, &mut buffer, &mut map, &mut flee_sums),
+            Err(Error::ZeroWeight(weightless.id))
+        );
+        assert!(map.is_empty() && buffer.is_empty());
+        assert_eq!(flee_sums.mean(), None);
+    }
+
+    #[test]
+    fn test_process_event_honours_weights() {
+        let mut buffer = FixedCircularBuffer::new(4);
+        let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
+        let heavy = Event::new(Uuid::new_v4())
+            .with_weight(3)
+            .with_timestamp(1_000);
+
+        process_event(&heavy, &mut buffer, &mut map, &mut flee_sums).unwrap();
+        process_event(
+            &Event::new(Uuid::new_v4()),
+            &mut buffer,
+            &mut map,
+            &mut flee_sums,
+        )
+        .unwrap();
+
+        // unweighted, the two opposite flee points would cancel out
+        let half = 1 << 31;
+        assert_eq!(flee_sums.mean(), Some(TorusPoint::new([half, half])));
+        assert_eq!(map[&heavy.id].weight, 3);
+        assert_eq!(map[&heavy.id].timestamp, Some(1_000));
+
+        // a repeat at weight 1 reweighs the copy already in the window
+        process_event(&Event::new(heavy.id), &mut buffer, &mut map, &mut flee_sums).unwrap();
+        let mut expected = CircularMeanAccumulator::new();
+        for id in &buffer {
+            expected
+                .add_weighted(&map[id].flee, map[id].weight)
+                .unwrap();
+        }
+        assert_eq!(flee_sums, expected);
+        // and carries the repeat's own timestamp, which it has none of
+        assert_eq!(map[&heavy.id].weight, 1);
+        assert_eq!(map[&heavy.id].timestamp, None);
+    }
+
+    #[test]
+    fn test_process_event_with_capacity_one_over_many_evictions() {
+        let mut buffer = FixedCircularBuffer::new(1);
+        let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
+
+        let repeated = Event::new(Uuid::new_v4());
+        for i in 0..2_000 {
+            let event = if i % 3 == 0 {
+                Event::new(repeated.id)
+            } else {
+                Event::new(Uuid::new_v4())
+            };
+            process_event(&event, &mut buffer, &mut map, &mut flee_sums).unwrap();
+
+            let info = map.get(&event.id).unwrap();
+            assert_eq!(buffer.len(), 1);
+            assert_eq!(flee_sums.count, 1);
+            assert_eq!(flee_sums.mean(), Some(info.flee));
+        }
+    }
+
+    #[test]
+    fn test_process_event_with_zero_capacity() {
+        let mut buffer = FixedCircularBuffer::new(0);
+        let mut map = HashMap::new();
+        let mut flee_sums = CircularMeanAccumulator::new();
+
+        let event = Event::new(Uuid::new_v4());
+        assert_eq!(
+            process_event(&event, &mut buffer, &mut map, &mut flee_sums),
+            Err(Error::ZeroCapacity)
+        );
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn test_process_event_with_id_missing_from_map() {
+        let missing_id = Uuid::new_v4();
+        let mut buffer = FixedCircularBuffer::new(4);
+        buffer.push_front(missing_id);
+
+        let event = Event::new(Uuid::new_v4());
+        let mut map = HashMap::new();
+        map.insert(
+            event.id,
+            EventInfo::new(TorusPoint::new([0, 0]), TorusPoint::new([0, 0])),
+        );
+        let mut flee_sums = CircularMeanAccumulator::new();
+
+        assert_eq!(
+            process_event(&event, &mut buffer, &mut map, &mut flee_sums),
+            Err(Error::MissingId(missing_id))
+        );
+        assert_eq!(buffer.len(), 1);
     }
 }
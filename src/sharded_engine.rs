@@ -0,0 +1,499 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::circular_mean_accumulator::CircularMeanAccumulator;
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use crate::process_event::{Event, EventInfo};
+use crate::snapshot::SnapshotError;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use uuid::Uuid;
+
+enum Command<const D: usize> {
+    // events tagged with their position in the caller's batch, and the sequence number of the
+    // batch's first event
+    Ingest(
+        u64,
+        Vec<(usize, Event)>,
+        Sender<Vec<(usize, Result<EventInfo<D>, Error>)>>,
+    ),
+    // every event numbered below this has been admitted
+    Evict(u64, Sender<()>),
+    Get(Uuid, Sender<Option<EventInfo<D>>>),
+    Window(Sender<Vec<(u64, Uuid, EventInfo<D>)>>),
+}
+
+// An engine split into shards by id hash, each an `Engine` of its own on a worker thread, so
+// events for different shards are processed in parallel. An id always lands on the same shard,
+// which keeps repeats local to it: a repeat moves relative to its shard's recent events.
+//
+// Every event is numbered in the order it is handed in, and the window is the `capacity` events
+// with the highest numbers, whichever shards hold them. Each shard keeps a circular-mean
+// accumulator over its own part of the window and publishes a copy after every event; new events
+// are placed opposite the merge of all shards' accumulators, so shards only ever lock each
+// other's published sums, never a shared window. Once a batch returns, every shard has dropped
+// what fell out of the window and the average is exactly that of the batch's last `capacity`
+// events, as in `Engine`. An event placed mid-batch may see a shard's part that is missing that
+// shard's earlier events of the batch, or still holds events pushed out by the batch so far.
+// With a single shard, or one event at a time, the average is exactly `Engine`'s.
+pub struct ShardedEngine<const D: usize = 2> {
+    shards: Vec<Sender<Command<D>>>,
+    workers: Vec<JoinHandle<()>>,
+    published: Arc<Published<D>>,
+    next_sequence: AtomicU64,
+}
+
+impl ShardedEngine {
+    pub fn new(shards: usize, capacity: usize) -> Self {
+        Self::with_torus(shards, capacity, Torus::FULL)
+    }
+}
+
+impl<const D: usize> ShardedEngine<D> {
+    pub fn with_torus(shards: usize, capacity: usize, torus: Torus<D>) -> Self {
+        let shards = shards.max(1);
+        let published = Arc::new(Published {
+            sums: (0..shards)
+                .map(|_| Mutex::new(CircularMeanAccumulator::with_torus(torus)))
+                .collect(),
+            torus,
+            frontier: AtomicU64::new(0),
+        });
+
+        let (senders, workers) = (0..shards)
+            .map(|shard| {
+                let (sender, receiver) = mpsc::channel();
+                let centroid = ShardCentroid {
+                    shard,
+                    capacity: capacity as u64,
+                    next: (0, Uuid::nil()),
+                    admissions: BTreeMap::new(),
+                    placements: HashMap::new(),
+                    sums: CircularMeanAccumulator::with_torus(torus),
+                    published: Arc::clone(&published),
+                };
+                let engine = Engine::with_strategy(capacity, centroid);
+                (sender, thread::spawn(move || run_shard(engine, receiver)))
+            })
+            .unzip();
+
+        ShardedEngine {
+            shards: senders,
+            workers,
+            published,
+            next_sequence: AtomicU64::new(0),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_of(&self, id: &Uuid) -> usize {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    pub fn ingest(&self, event: &Event) -> Result<EventInfo<D>, Error> {
+        self.ingest_batch(vec![event.clone()])
+            .pop()
+            .unwrap_or(Err(Error::ShardStopped))
+    }
+
+    // Hands each shard its share of `events` at once, so the shards work through them in
+    // parallel, and returns the results in the order of `events`. Events for the same shard are
+    // processed in the order given. Events for a shard whose worker has stopped fail with
+    // `Error::ShardStopped`.
+    pub fn ingest_batch(&self, events: Vec<Event>) -> Vec<Result<EventInfo<D>, Error>> {
+        let count = events.len();
+        let first = self
+            .next_sequence
+            .fetch_add(count as u64, Ordering::Relaxed);
+        let mut batches: Vec<Vec<(usize, Event)>> = vec![Vec::new(); self.shards.len()];
+        for (position, event) in events.into_iter().enumerate() {
+            batches[self.shard_of(&event.id)].push((position, event));
+        }
+
+        let (reply, replies) = mpsc::channel();
+        for (shard, batch) in batches.into_iter().enumerate() {
+            if !batch.is_empty() {
+                let _ = self.shards[shard].send(Command::Ingest(first, batch, reply.clone()));
+            }
+        }
+        // a stopped worker drops its copy unanswered, so this ends once every live one replied
+        drop(reply);
+
+        let mut results = vec![Err(Error::ShardStopped); count];
+        for batch in replies {
+            for (position, result) in batch {
+                results[position] = result;
+            }
+        }
+
+        // only now can the shards that held none of the batch's events drop what it pushed out
+        let (done, acknowledged) = mpsc::channel();
+        for shard in &self.shards {
+            let _ = shard.send(Command::Evict(first + count as u64, done.clone()));
+        }
+        drop(done);
+        acknowledged.iter().count();
+        results
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Option<EventInfo<D>>, Error> {
+        let (reply, response) = mpsc::channel();
+        self.shards[self.shard_of(id)]
+            .send(Command::Get(*id, reply))
+            .map_err(|_| Error::ShardStopped)?;
+        response.recv().map_err(|_| Error::ShardStopped)
+    }
+
+    // The ids in the window with their current placements, newest first.
+    pub fn window(&self) -> Result<Vec<(Uuid, EventInfo<D>)>, Error> {
+        let (reply, replies) = mpsc::channel();
+        for shard in &self.shards {
+            shard
+                .send(Command::Window(reply.clone()))
+                .map_err(|_| Error::ShardStopped)?;
+        }
+        drop(reply);
+
+        let parts: Vec<_> = replies.iter().collect();
+        if parts.len() < self.shards.len() {
+            return Err(Error::ShardStopped);
+        }
+        let mut window: Vec<_> = parts.into_iter().flatten().collect();
+        window.sort_unstable_by_key(|(sequence, _, _)| std::cmp::Reverse(*sequence));
+        Ok(window.into_iter().map(|(_, id, info)| (id, info)).collect())
+    }
+
+    // The circular mean over the window: every shard's published accumulator, merged.
+    pub fn flee_average(&self) -> Option<TorusPoint<D>> {
+        self.published.merged(None).ok()?.mean()
+    }
+}
+
+impl<const D: usize> Drop for ShardedEngine<D> {
+    fn drop(&mut self) {
+        // closing the channels lets every worker finish what it has and return
+        self.shards.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run_shard<const D: usize>(
+    mut engine: Engine<D, ShardCentroid<D>>,
+    commands: Receiver<Command<D>>,
+) {
+    for command in commands {
+        match command {
+            Command::Ingest(first, batch, reply) => {
+                let mut results = Vec::with_capacity(batch.len());
+                for (position, event) in batch {
+                    engine.centroid.next = (first + position as u64, event.id);
+                    results.push((position, engine.ingest(&event)));
+                    engine.centroid.publish();
+                }
+                let _ = reply.send(results);
+            }
+            Command::Evict(frontier, done) => {
+                engine.centroid.evict(frontier);
+                engine.centroid.publish();
+                let _ = done.send(());
+            }
+            Command::Get(id, reply) => {
+                let _ = reply.send(engine.get(&id).copied());
+            }
+            Command::Window(reply) => {
+                let centroid = &engine.centroid;
+                let part = centroid
+                    .admissions
+                    .iter()
+                    .map(|(sequence, id)| (*sequence, *id, centroid.placements[id].0))
+                    .collect();
+                let _ = reply.send(part);
+            }
+        }
+    }
+}
+
+// What the shards share: each one's latest accumulator, and one past the highest sequence number
+// any shard has admitted.
+struct Published<const D: usize> {
+    sums: Box<[Mutex<CircularMeanAccumulator<D>>]>,
+    torus: Torus<D>,
+    frontier: AtomicU64,
+}
+
+impl<const D: usize> Published<D> {
+    // Every shard's published sums merged, with `own` standing in for one shard's.
+    fn merged(
+        &self,
+        own: Option<(usize, &CircularMeanAccumulator<D>)>,
+    ) -> Result<CircularMeanAccumulator<D>, Error> {
+        let mut merged = CircularMeanAccumulator::with_torus(self.torus);
+        for (shard, sums) in self.sums.iter().enumerate() {
+            match own {
+                Some((own_shard, own_sums)) if own_shard == shard => merged.merge(own_sums)?,
+                _ => merged.merge(&lock(sums))?,
+            }
+        }
+        Ok(merged)
+    }
+}
+
+// An accumulator is always whole, even if a thread panicked while holding its lock.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// A shard's view of the centroid: its own part of the window, which it is told the sequence
+// number and id of each event for as it ingests it, merged with the other shards' parts.
+#[derive(Clone)]
+pub(crate) struct ShardCentroid<const D: usize> {
+    shard: usize,
+    capacity: u64,
+    next: (u64, Uuid),
+    // this shard's events among the last `capacity`, by sequence number
+    admissions: BTreeMap<u64, Uuid>,
+    // the current placement of every id in `admissions`, and how often it occurs there
+    placements: HashMap<Uuid, (EventInfo<D>, usize)>,
+    sums: CircularMeanAccumulator<D>,
+    published: Arc<Published<D>>,
+}
+
+impl<const D: usize> ShardCentroid<D> {
+    fn publish(&self) {
+        *lock(&self.published.sums[self.shard]) = self.sums;
+    }
+
+    // Drops the events that fall out of the window once every event before `frontier` is in.
+    fn evict(&mut self, frontier: u64) {
+        let bound = frontier.saturating_sub(self.capacity);
+        while let Some((&sequence, &id)) = self.admissions.first_key_value() {
+            if sequence >= bound {
+                break;
+            }
+            let (placement, occurrences) = self.placements[&id];
+            // only what was added is removed, so this cannot fail
+            let _ = self.sums.remove_weighted(&placement.flee, placement.weight);
+            self.admissions.remove(&sequence);
+            if occurrences == 1 {
+                self.placements.remove(&id);
+            } else {
+                self.placements.insert(id, (placement, occurrences - 1));
+            }
+        }
+    }
+}
+
+impl<const D: usize> CentroidStrategy<D> for ShardCentroid<D> {
+    const NAME: &'static str = "sharded";
+
+    fn torus(&self) -> &Torus<D> {
+        self.sums.torus()
+    }
+
+    fn admit(
+        &mut self,
+        event_map: &HashMap<Uuid, EventInfo<D>>,
+        _: &FixedCircularBuffer<Uuid>,
+        latest_uuid: &Uuid,
+    ) -> Result<(), Error> {
+        let latest = event_map
+            .get(latest_uuid)
+            .ok_or(Error::MissingId(*latest_uuid))?;
+        let (sequence, _) = self.next;
+        let frontier = self.published.frontier.load(Ordering::Acquire);
+        // a later event may already have pushed this one out of the window
+        if sequence >= frontier.max(sequence + 1).saturating_sub(self.capacity) {
+            let mut sums = self.sums;
+            sums.add_weighted(&latest.flee, latest.weight)?;
+            self.sums = sums;
+            self.admissions.insert(sequence, *latest_uuid);
+            self.placements
+                .entry(*latest_uuid)
+                .or_insert((*latest, 0))
+                .1 += 1;
+        }
+        self.published
+            .frontier
+            .fetch_max(sequence + 1, Ordering::AcqRel);
+        self.evict(frontier.max(sequence + 1));
+        Ok(())
+    }
+
+    // only the id being ingested is ever moved
+    fn moved(&mut self, old: &EventInfo<D>, new: &EventInfo<D>, _: usize) -> Result<(), Error> {
+        let (_, id) = self.next;
+        if let Some((placement, occurrences)) = self.placements.get_mut(&id) {
+            self.sums.moved(old, new, *occurrences)?;
+            *placement = *new;
+        }
+        Ok(())
+    }
+
+    fn centroid(&self) -> Option<TorusPoint<D>> {
+        // sums too large to merge cannot be averaged, so they count as no centroid
+        self.published
+            .merged(Some((self.shard, &self.sums)))
+            .ok()?
+            .mean()
+    }
+
+    fn to_snapshot(&self) -> Value {
+        self.sums.to_snapshot()
+    }
+
+    fn from_snapshot(_: Torus<D>, _: &Value) -> Result<Self, SnapshotError> {
+        Err(SnapshotError::Invalid(
+            "a shard's centroid cannot be restored apart from its engine".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(count: usize) -> Vec<Event> {
+        let pool: Vec<Uuid> = (0..count / 3 + 1).map(|_| Uuid::new_v4()).collect();
+        (0..count)
+            .map(|i| Event::new(pool[i * 7 % pool.len()]).with_weight(i as u32 % 3 + 1))
+            .collect()
+    }
+
+    #[test]
+    fn test_single_shard_matches_engine() {
+        let torus = Torus::new([1 << 20, 1 << 20]).unwrap();
+        let sharded = ShardedEngine::with_torus(1, 8, torus);
+        let mut engine = Engine::with_torus(8, torus);
+
+        let events = events(200);
+        let results = sharded.ingest_batch(events.clone());
+        for (event, result) in events.iter().zip(results) {
+            assert_eq!(result, engine.ingest(event));
+        }
+        assert_eq!(sharded.flee_average(), engine.flee_average());
+        assert_eq!(
+            sharded.get(&events[0].id),
+            Ok(engine.get(&events[0].id).copied())
+        );
+    }
+
+    fn window_mean(sharded: &ShardedEngine, torus: Torus) -> Option<TorusPoint> {
+        let mut expected = CircularMeanAccumulator::with_torus(torus);
+        for (id, info) in sharded.window().unwrap() {
+            assert_eq!(sharded.get(&id), Ok(Some(info)));
+            expected.add_weighted(&info.flee, info.weight).unwrap();
+        }
+        expected.mean()
+    }
+
+    #[test]
+    fn test_window_holds_the_last_events_across_shards() {
+        let torus = Torus::new([1000, 1000]).unwrap();
+        let sharded = ShardedEngine::with_torus(4, 5, torus);
+        let events = events(300);
+
+        for (batch, chunk) in events.chunks(37).enumerate() {
+            for result in sharded.ingest_batch(chunk.to_vec()) {
+                result.unwrap();
+            }
+            // whichever shard got there first, the window is the last events so far, newest first
+            let window: Vec<Uuid> = sharded
+                .window()
+                .unwrap()
+                .iter()
+                .map(|(id, _)| *id)
+                .collect();
+            let so_far = &events[..batch * 37 + chunk.len()];
+            let last: Vec<Uuid> = so_far.iter().rev().take(5).map(|event| event.id).collect();
+            assert_eq!(window, last);
+            assert_eq!(sharded.flee_average(), window_mean(&sharded, torus));
+        }
+        for event in &events[..10] {
+            assert!(sharded.get(&event.id).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_one_event_at_a_time_averages_like_engine() {
+        let torus = Torus::new([1 << 20, 1 << 20]).unwrap();
+        let sharded = ShardedEngine::with_torus(3, 6, torus);
+        let mut engine = Engine::with_torus(6, torus);
+
+        // without repeats, which move relative to their own shard, placements match too
+        for _ in 0..40 {
+            let event = Event::new(Uuid::new_v4());
+            assert_eq!(sharded.ingest(&event), engine.ingest(&event));
+            assert_eq!(sharded.flee_average(), engine.flee_average());
+        }
+
+        for event in events(60) {
+            sharded.ingest(&event).unwrap();
+        }
+        assert_eq!(sharded.window().unwrap().len(), 6);
+        assert_eq!(sharded.flee_average(), window_mean(&sharded, torus));
+    }
+
+    #[test]
+    fn test_repeats_stay_on_their_shard() {
+        let sharded = ShardedEngine::new(3, 4);
+        let id = Uuid::new_v4();
+        sharded.ingest(&Event::new(id)).unwrap();
+        let repeated = sharded.ingest(&Event::new(id).with_weight(5)).unwrap();
+
+        assert_eq!(sharded.get(&id), Ok(Some(repeated)));
+        assert_eq!(repeated.weight, 5);
+        assert_eq!(sharded.window().unwrap().len(), 2);
+        assert_eq!(ShardedEngine::new(0, 4).shards(), 1);
+    }
+
+    #[test]
+    fn test_shards_merge_their_own_parts_of_the_window() {
+        let torus = Torus::new([1000, 1000]).unwrap();
+        let sharded = ShardedEngine::with_torus(4, 10, torus);
+        for result in sharded.ingest_batch(events(50)) {
+            result.unwrap();
+        }
+
+        // each shard publishes the sums of only its own ids in the window
+        let window = sharded.window().unwrap();
+        for (shard, published) in sharded.published.sums.iter().enumerate() {
+            let mut expected = CircularMeanAccumulator::with_torus(torus);
+            for (id, info) in &window {
+                if sharded.shard_of(id) == shard {
+                    expected.add_weighted(&info.flee, info.weight).unwrap();
+                }
+            }
+            assert_eq!(*lock(published), expected);
+        }
+        assert_eq!(sharded.flee_average(), window_mean(&sharded, torus));
+    }
+
+    #[test]
+    fn test_a_stopped_shard_is_an_error() {
+        let mut sharded = ShardedEngine::new(2, 4);
+        let id = Uuid::new_v4();
+        // closing one shard's channel stops its worker, as a panic in it would
+        let (closed, _) = mpsc::channel();
+        let shard = sharded.shard_of(&id);
+        sharded.shards[shard] = closed;
+
+        assert_eq!(sharded.ingest(&Event::new(id)), Err(Error::ShardStopped));
+        assert_eq!(sharded.get(&id), Err(Error::ShardStopped));
+        assert_eq!(sharded.window(), Err(Error::ShardStopped));
+    }
+}
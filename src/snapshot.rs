@@ -0,0 +1,405 @@
+use crate::centroid_strategy::CentroidStrategy;
+use crate::engine::Engine;
+use crate::eviction_policy::{EvictionPolicy, EvictionTracker};
+use crate::process_event::EventInfo;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    // the text is not JSON at all
+    Json(serde_json::Error),
+    // written by a newer or unknown format
+    UnsupportedVersion(u64),
+    // the snapshot was written for a different number of dimensions
+    Dimensions { expected: usize, found: usize },
+    // a field is missing or has the wrong shape
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "snapshot I/O failed: {}", error),
+            SnapshotError::Json(error) => write!(f, "snapshot is not valid JSON: {}", error),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Dimensions { expected, found } => write!(
+                f,
+                "snapshot has {} dimensions but {} were expected",
+                found, expected
+            ),
+            SnapshotError::Invalid(field) => write!(f, "invalid snapshot field: {}", field),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(error: serde_json::Error) -> Self {
+        SnapshotError::Json(error)
+    }
+}
+
+fn invalid(field: &str) -> SnapshotError {
+    SnapshotError::Invalid(field.to_string())
+}
+
+// Everything an engine needs to carry on exactly where it stopped: the window in order, every
+// placement, the raw fixed point flee sums and the eviction clock. The spatial indexes are rebuilt
+// from the placements and the eviction hook is not saved.
+impl<const D: usize, S: CentroidStrategy<D>> Engine<D, S> {
+    pub fn to_snapshot(&self) -> Value {
+        let mut placements: Vec<(&Uuid, &EventInfo<D>)> = self.map.iter().collect();
+        placements.sort_by_key(|(id, _)| **id);
+        let mut last_ingested: Vec<(&Uuid, &u64)> = self.evictions.last_ingested().iter().collect();
+        last_ingested.sort_by_key(|(id, _)| **id);
+
+        json!({
+            "version": SNAPSHOT_VERSION,
+            "dimensions": D,
+            "torus": self.torus().sizes().to_vec(),
+            "capacity": self.buffer.capacity(),
+            // front (newest) to back (oldest)
+            "buffer": self.buffer().into_iter().map(Uuid::to_string).collect::<Vec<_>>(),
+            "placements": placements
+                .into_iter()
+                .map(|(id, info)| info.to_json(id))
+                .collect::<Vec<_>>(),
+            "strategy": S::NAME,
+            "flee_sums": self.centroid.to_snapshot(),
+            "eviction": {
+                "drop_unbuffered": self.eviction_policy.drop_unbuffered,
+                "ttl": self.eviction_policy.ttl,
+                "max_entries": self.eviction_policy.max_entries,
+                "tick": self.evictions.tick(),
+                "last_ingested": last_ingested
+                    .into_iter()
+                    .map(|(id, tick)| json!({ "id": id.to_string(), "tick": tick }))
+                    .collect::<Vec<_>>(),
+            },
+        })
+    }
+
+    pub fn from_snapshot(snapshot: &Value) -> Result<Self, SnapshotError> {
+        let root = snapshot.as_object().ok_or_else(|| invalid("root"))?;
+        let version = get_u64(root, "version")?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let dimensions = get_u64(root, "dimensions")? as usize;
+        if dimensions != D {
+            return Err(SnapshotError::Dimensions {
+                expected: D,
+                found: dimensions,
+            });
+        }
+
+        let sizes = get_array(root, "torus")?
+            .iter()
+            .map(|size| size.as_u64().ok_or_else(|| invalid("torus")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let sizes: [u64; D] = sizes.try_into().map_err(|_| invalid("torus"))?;
+        let torus = Torus::new(sizes).map_err(|error| SnapshotError::Invalid(error.to_string()))?;
+        let capacity = get_u64(root, "capacity")? as usize;
+
+        // snapshots from before strategies were pluggable hold the windowed mean
+        let strategy = root
+            .get("strategy")
+            .and_then(Value::as_str)
+            .unwrap_or("windowed");
+        if strategy != S::NAME {
+            return Err(SnapshotError::Invalid(format!(
+                "strategy {} where {} was expected",
+                strategy,
+                S::NAME
+            )));
+        }
+        let flee_sums = root.get("flee_sums").ok_or_else(|| invalid("flee_sums"))?;
+        let centroid = S::from_snapshot(torus, flee_sums)?;
+        let mut engine = Engine::with_strategy(capacity, centroid);
+
+        let buffer = get_array(root, "buffer")?
+            .iter()
+            .map(|id| parse_uuid(id, "buffer"))
+            .collect::<Result<Vec<_>, _>>()?;
+        if buffer.len() > capacity {
+            return Err(invalid("buffer"));
+        }
+        for id in buffer.iter().rev() {
+            engine.buffer.push_front(*id);
+        }
+
+        for placement in get_array(root, "placements")? {
+            let placement = placement.as_object().ok_or_else(|| invalid("placements"))?;
+            let id = parse_uuid(placement.get("id").unwrap_or(&Value::Null), "placements")?;
+            // placements saved before events had weights count once and carry no timestamp
+            let info = EventInfo {
+                follow: parse_point(placement.get("follow"))?,
+                flee: parse_point(placement.get("flee"))?,
+                weight: get_optional_u64(placement, "weight")?
+                    .map(u32::try_from)
+                    .transpose()
+                    .map_err(|_| invalid("weight"))?
+                    .unwrap_or(1),
+                timestamp: get_optional_u64(placement, "timestamp")?,
+            };
+            engine.follow_index.insert(id, info.follow);
+            engine.flee_index.insert(id, info.flee);
+            engine.map.insert(id, info);
+        }
+        if let Some(missing) = buffer.iter().find(|id| !engine.map.contains_key(id)) {
+            return Err(SnapshotError::Invalid(format!(
+                "buffer id {} has no placement",
+                missing
+            )));
+        }
+
+        let eviction = get_object(root, "eviction")?;
+        engine.eviction_policy = EvictionPolicy {
+            drop_unbuffered: eviction
+                .get("drop_unbuffered")
+                .and_then(Value::as_bool)
+                .ok_or_else(|| invalid("eviction.drop_unbuffered"))?,
+            ttl: get_optional_u64(eviction, "ttl")?,
+            max_entries: get_optional_u64(eviction, "max_entries")?.map(|max| max as usize),
+        };
+        let mut last_ingested = HashMap::new();
+        for entry in get_array(eviction, "last_ingested")? {
+            let entry = entry.as_object().ok_or_else(|| invalid("last_ingested"))?;
+            let id = parse_uuid(entry.get("id").unwrap_or(&Value::Null), "last_ingested")?;
+            last_ingested.insert(id, get_u64(entry, "tick")?);
+        }
+        engine.evictions =
+            EvictionTracker::restore(get_u64(eviction, "tick")?, last_ingested, &buffer);
+
+        Ok(engine)
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        write_snapshot_file(path.as_ref(), &self.to_snapshot())
+    }
+
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Self::from_snapshot(&read_snapshot_file(path.as_ref())?)
+    }
+}
+
+// Writes next to `path` first and renames, so a crash never leaves a half written snapshot.
+pub(crate) fn write_snapshot_file(path: &Path, snapshot: &Value) -> Result<(), SnapshotError> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let mut file = fs::File::create(&partial)?;
+    file.write_all(snapshot.to_string().as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+pub(crate) fn read_snapshot_file(path: &Path) -> Result<Value, SnapshotError> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn get_u64(object: &Map<String, Value>, field: &str) -> Result<u64, SnapshotError> {
+    object
+        .get(field)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid(field))
+}
+
+fn get_optional_u64(
+    object: &Map<String, Value>,
+    field: &str,
+) -> Result<Option<u64>, SnapshotError> {
+    match object.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_u64().map(Some).ok_or_else(|| invalid(field)),
+    }
+}
+
+fn get_array<'a>(
+    object: &'a Map<String, Value>,
+    field: &str,
+) -> Result<&'a Vec<Value>, SnapshotError> {
+    object
+        .get(field)
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid(field))
+}
+
+fn get_object<'a>(
+    object: &'a Map<String, Value>,
+    field: &str,
+) -> Result<&'a Map<String, Value>, SnapshotError> {
+    object
+        .get(field)
+        .and_then(Value::as_object)
+        .ok_or_else(|| invalid(field))
+}
+
+fn parse_uuid(value: &Value, field: &str) -> Result<Uuid, SnapshotError> {
+    value
+        .as_str()
+        .and_then(|id| Uuid::parse_str(id).ok())
+        .ok_or_else(|| invalid(field))
+}
+
+fn parse_point<const D: usize>(value: Option<&Value>) -> Result<TorusPoint<D>, SnapshotError> {
+    let coordinates = value
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("point"))?
+        .iter()
+        .map(|coordinate| {
+            coordinate
+                .as_u64()
+                .and_then(|coordinate| u32::try_from(coordinate).ok())
+                .ok_or_else(|| invalid("point"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    coordinates
+        .try_into()
+        .map(TorusPoint::new)
+        .map_err(|_| invalid("point"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exponential_mean::{ExponentialMean, HalfLife};
+    use crate::process_event::Event;
+
+    fn replay<const D: usize, S: CentroidStrategy<D>>(
+        engine: &mut Engine<D, S>,
+        ids: &[Uuid],
+    ) -> Vec<EventInfo<D>> {
+        ids.iter()
+            .map(|id| engine.ingest(&Event::new(*id)).unwrap())
+            .collect()
+    }
+
+    // a few ids that keep coming back, so repeats and evictions both happen
+    fn event_ids(count: usize) -> Vec<Uuid> {
+        let pool: Vec<Uuid> = (0..7).map(|_| Uuid::new_v4()).collect();
+        (0..count)
+            .map(|i| {
+                if i % 3 == 0 {
+                    Uuid::new_v4()
+                } else {
+                    pool[i * 5 % pool.len()]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_snapshot_restore_continues_bit_identically() {
+        let mut original = Engine::with_torus(5, Torus::new([1 << 20, 12345]).unwrap());
+        original.set_eviction_policy(EvictionPolicy {
+            max_entries: Some(12),
+            ttl: Some(40),
+            ..EvictionPolicy::default()
+        });
+        let ids = event_ids(400);
+        replay(&mut original, &ids[..200]);
+
+        let text = original.to_snapshot().to_string();
+        let mut restored =
+            Engine::<2>::from_snapshot(&serde_json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(restored.to_snapshot().to_string(), text);
+
+        assert_eq!(
+            replay(&mut restored, &ids[200..]),
+            replay(&mut original, &ids[200..])
+        );
+        assert_eq!(restored.flee_average(), original.flee_average());
+        assert_eq!(restored.to_snapshot(), original.to_snapshot());
+    }
+
+    #[test]
+    fn test_snapshot_restores_exponential_strategy() {
+        let torus = Torus::new([4096, 4096]).unwrap();
+        let strategy = ExponentialMean::new(torus, HalfLife::Events(3.0)).unwrap();
+        let mut original = Engine::with_strategy(4, strategy);
+        let ids = event_ids(60);
+        replay(&mut original, &ids[..30]);
+
+        let snapshot = original.to_snapshot();
+        let mut restored = Engine::<2, ExponentialMean>::from_snapshot(&snapshot).unwrap();
+        assert!(matches!(
+            Engine::<2>::from_snapshot(&snapshot),
+            Err(SnapshotError::Invalid(_))
+        ));
+
+        assert_eq!(
+            replay(&mut restored, &ids[30..]),
+            replay(&mut original, &ids[30..])
+        );
+        assert_eq!(restored.strategy(), original.strategy());
+    }
+
+    #[test]
+    fn test_snapshot_round_trips_through_a_file() {
+        let dir = std::env::temp_dir().join(format!("inverse-pairs-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("engine.json");
+
+        let mut original = Engine::<3>::with_torus(4, Torus::FULL);
+        replay(&mut original, &event_ids(30));
+        original.save_snapshot(&path).unwrap();
+        let restored = Engine::<3>::load_snapshot(&path).unwrap();
+
+        assert_eq!(restored.to_snapshot(), original.to_snapshot());
+        assert_eq!(
+            restored.nearest_follows(&TorusPoint::ORIGIN, 3),
+            original.nearest_follows(&TorusPoint::ORIGIN, 3)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_rejects_mismatches() {
+        let mut engine = Engine::new(3);
+        replay(&mut engine, &event_ids(5));
+        let snapshot = engine.to_snapshot();
+
+        assert!(matches!(
+            Engine::<3>::from_snapshot(&snapshot),
+            Err(SnapshotError::Dimensions {
+                expected: 3,
+                found: 2
+            })
+        ));
+
+        let mut newer = snapshot.clone();
+        newer["version"] = json!(SNAPSHOT_VERSION + 1);
+        assert!(matches!(
+            Engine::<2>::from_snapshot(&newer),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+
+        let mut orphaned = snapshot;
+        orphaned["placements"] = json!([]);
+        assert!(matches!(
+            Engine::<2>::from_snapshot(&orphaned),
+            Err(SnapshotError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::process_event::{Event, EventInfo};
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use uuid::Uuid;
+
+// The protocol spoken over the socket by `inverse-pairs-socket`: newline-delimited JSON, one
+// request and then one response per line, as many as the client likes on a connection.
+//
+//   {"command": "ingest", "event": {"id": ..., "timestamp"?, "weight"?, "payload"?}}
+//       (a payload that is not UTF-8 goes as "payload_bytes", an array of byte values)
+//   {"command": "get", "id": ...}
+//   {"command": "stats"}
+//   {"command": "snapshot"}
+//
+// Each is answered with {"ok": RESULT} or {"error": MESSAGE}. Placements have the shape of
+// `EventInfo::to_json`, and `get` answers null for an id with no placement.
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    // the server understood the request and refused it
+    Server(String),
+    // the server answered with something that is not a response
+    Protocol(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(error) => write!(f, "{}", error),
+            ClientError::Server(message) => write!(f, "server error: {}", message),
+            ClientError::Protocol(message) => write!(f, "malformed response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::Io(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EngineStats {
+    pub capacity: usize,
+    // ids in the window, at most `capacity`
+    pub window: usize,
+    // ids with a placement, in the window or not
+    pub placements: usize,
+    pub flee_average: Option<TorusPoint>,
+}
+
+// One connection to an `inverse-pairs-socket` server. Requests are answered in order, so a client
+// is used from one thread at a time; open more connections to talk to the server in parallel.
+pub struct SocketClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl SocketClient {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(SocketClient { reader, writer })
+    }
+
+    pub fn ingest(&mut self, event: &Event) -> Result<EventInfo, ClientError> {
+        let placement = self.call(json!({ "command": "ingest", "event": event.to_json() }))?;
+        EventInfo::from_json(&placement)
+            .map(|(_, info)| info)
+            .map_err(ClientError::Protocol)
+    }
+
+    pub fn get(&mut self, id: &Uuid) -> Result<Option<EventInfo>, ClientError> {
+        match self.call(json!({ "command": "get", "id": id.to_string() }))? {
+            Value::Null => Ok(None),
+            placement => EventInfo::from_json(&placement)
+                .map(|(_, info)| Some(info))
+                .map_err(ClientError::Protocol),
+        }
+    }
+
+    pub fn stats(&mut self) -> Result<EngineStats, ClientError> {
+        let stats = self.call(json!({ "command": "stats" }))?;
+        let count = |field: &str| {
+            stats
+                .get(field)
+                .and_then(Value::as_u64)
+                .map(|count| count as usize)
+                .ok_or_else(|| ClientError::Protocol(format!("stats have no \"{}\"", field)))
+        };
+        let flee_average = match stats.get("flee_average") {
+            Some(Value::Null) => None,
+            Some(Value::Array(coordinates)) => {
+                let coordinate = |axis: usize| {
+                    coordinates
+                        .get(axis)
+                        .and_then(Value::as_u64)
+                        .and_then(|coordinate| u32::try_from(coordinate).ok())
+                };
+                match (coordinates.len(), coordinate(0), coordinate(1)) {
+                    (2, Some(x), Some(y)) => Some(TorusPoint::new([x, y])),
+                    _ => return Err(ClientError::Protocol("invalid \"flee_average\"".into())),
+                }
+            }
+            _ => {
+                return Err(ClientError::Protocol(
+                    "stats have no \"flee_average\"".into(),
+                ))
+            }
+        };
+
+        Ok(EngineStats {
+            capacity: count("capacity")?,
+            window: count("window")?,
+            placements: count("placements")?,
+            flee_average,
+        })
+    }
+
+    // The engine's snapshot, which `Engine::from_snapshot` restores.
+    pub fn snapshot(&mut self) -> Result<Value, ClientError> {
+        self.call(json!({ "command": "snapshot" }))
+    }
+
+    fn call(&mut self, request: Value) -> Result<Value, ClientError> {
+        writeln!(self.writer, "{}", request)?;
+        self.writer.flush()?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ClientError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let mut response: Value = serde_json::from_str(&line)
+            .map_err(|error| ClientError::Protocol(error.to_string()))?;
+        if let Some(message) = response.get("error") {
+            return Err(ClientError::Server(
+                message.as_str().unwrap_or_default().to_string(),
+            ));
+        }
+        response
+            .get_mut("ok")
+            .map(Value::take)
+            .ok_or_else(|| ClientError::Protocol("neither \"ok\" nor \"error\"".into()))
+    }
+}
@@ -1,126 +1,158 @@
-pub fn toroidal_distance_squared(x1: u32, y1: u32, x2: u32, y2: u32) -> u64 {
-    const SIZE: u32 = u32::MAX;
-
-    let dx = if x1 > x2 { x1 - x2 } else { x2 - x1 };
-    let dy = if y1 > y2 { y1 - y2 } else { y2 - y1 };
-
-    let wrapped_dx = if dx > SIZE / 2 { SIZE - dx } else { dx };
-    let wrapped_dy = if dy > SIZE / 2 { SIZE - dy } else { dy };
-
-    (wrapped_dx as u64).pow(2) + (wrapped_dy as u64).pow(2)
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+
+pub fn toroidal_distance_squared<const D: usize>(
+    torus: &Torus<D>,
+    a: &TorusPoint<D>,
+    b: &TorusPoint<D>,
+) -> u128 {
+    // each offset is at most 2^31, so even many squared axes fit in a u128
+    torus
+        .offset(a, b)
+        .iter()
+        .map(|delta| (delta.unsigned_abs() as u128).pow(2))
+        .sum()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn distance(x1: u32, y1: u32, x2: u32, y2: u32) -> u128 {
+        toroidal_distance_squared(
+            &Torus::FULL,
+            &TorusPoint::new([x1, y1]),
+            &TorusPoint::new([x2, y2]),
+        )
+    }
+
     #[test]
     fn test_toroidal_distance_normal_1() {
-        assert_eq!(toroidal_distance_squared(0, 0, 3, 4), 25);
+        assert_eq!(distance(0, 0, 3, 4), 25);
     }
 
     #[test]
     fn test_toroidal_distance_normal_2() {
-        assert_eq!(toroidal_distance_squared(5, 5, 10, 10), 50);
+        assert_eq!(distance(5, 5, 10, 10), 50);
     }
 
     #[test]
     fn test_toroidal_distance_edge_1() {
-        assert_eq!(
-            toroidal_distance_squared(0, 0, std::u32::MAX, std::u32::MAX),
-            0
-        );
+        assert_eq!(distance(0, 0, u32::MAX, u32::MAX), 2);
     }
 
     #[test]
     fn test_toroidal_distance_edge_2() {
-        assert_eq!(
-            toroidal_distance_squared(std::u32::MAX, std::u32::MAX, 0, 0),
-            0
-        );
+        assert_eq!(distance(u32::MAX, u32::MAX, 0, 0), 2);
     }
 
     #[test]
     fn test_toroidal_distance_edge_3() {
-        assert_eq!(
-            toroidal_distance_squared(std::u32::MAX, std::u32::MAX, 1, 1),
-            2
-        );
+        assert_eq!(distance(u32::MAX, u32::MAX, 1, 1), 8);
     }
 
     #[test]
     fn test_toroidal_distance_edge_4() {
-        assert_eq!(toroidal_distance_squared(0, 0, std::u32::MAX, 0), 0);
+        assert_eq!(distance(0, 0, u32::MAX, 0), 1);
     }
 
     #[test]
     fn test_toroidal_distance_edge_5() {
-        assert_eq!(toroidal_distance_squared(std::u32::MAX, 0, 0, 0), 0);
+        assert_eq!(distance(u32::MAX, 0, 0, 0), 1);
     }
 
     #[test]
     fn test_toroidal_distance_edge_6() {
-        assert_eq!(
-            toroidal_distance_squared(std::u32::MAX, 0, 0, std::u32::MAX),
-            0
-        );
+        assert_eq!(distance(u32::MAX, 0, 0, u32::MAX), 2);
     }
 
     #[test]
     fn test_toroidal_distance_edge_7() {
-        assert_eq!(
-            toroidal_distance_squared(std::u32::MAX, std::u32::MAX, 0, std::u32::MAX),
-            0
-        );
+        assert_eq!(distance(u32::MAX, u32::MAX, 0, u32::MAX), 1);
     }
 
     #[test]
     fn test_toroidal_distance_edge_8() {
-        assert_eq!(
-            toroidal_distance_squared(0, std::u32::MAX, std::u32::MAX, std::u32::MAX),
-            0
-        );
+        assert_eq!(distance(0, u32::MAX, u32::MAX, u32::MAX), 1);
     }
 
     #[test]
     fn test_toroidal_distance_squared_small_values() {
         // Test case 1: Small input values
-        assert_eq!(toroidal_distance_squared(10, 20, 30, 40), 800);
+        assert_eq!(distance(10, 20, 30, 40), 800);
     }
 
     #[test]
     fn test_toroidal_distance_squared_inputs_close_to_size() {
         // Test case 2: Inputs close to SIZE
-        assert_eq!(
-            toroidal_distance_squared(std::u32::MAX - 20, std::u32::MAX - 30, 10, 20),
-            3400
-        );
+        assert_eq!(distance(u32::MAX - 20, u32::MAX - 30, 10, 20), 3562);
     }
 
     #[test]
     fn test_toroidal_distance_squared_inputs_close_to_size_with_wrapping() {
         // Test case 3: Inputs close to SIZE with wrapping
-        assert_eq!(
-            toroidal_distance_squared(std::u32::MAX - 20, 30, 10, std::u32::MAX - 20),
-            3400
-        );
+        assert_eq!(distance(u32::MAX - 20, 30, 10, u32::MAX - 20), 3562);
     }
 
     #[test]
     fn test_toroidal_distance_squared_inputs_wrapping_around_several_times() {
         // Test case 4: Inputs wrapping around several times
-        assert_eq!(
-            toroidal_distance_squared(10, 20, std::u32::MAX - 30, std::u32::MAX - 40),
-            5200
-        );
+        assert_eq!(distance(10, 20, u32::MAX - 30, u32::MAX - 40), 5402);
     }
 
     #[test]
     fn test_toroidal_distance_squared_inputs_wrapping_around_several_times_with_larger_distance() {
         // Test case 5: Inputs wrapping around several times with larger distance
+        assert_eq!(distance(10, 20, u32::MAX - 10, u32::MAX - 20), 2122);
+    }
+
+    #[test]
+    fn test_toroidal_distance_squared_furthest_points_on_full_torus() {
+        assert_eq!(distance(0, 0, 1 << 31, 1 << 31), 1 << 63);
+    }
+
+    #[test]
+    fn test_toroidal_distance_squared_on_small_torus() {
+        let torus = Torus::new([1024, 1024]).unwrap();
+        let origin = TorusPoint::ORIGIN;
+        assert_eq!(
+            toroidal_distance_squared(&torus, &origin, &TorusPoint::new([1023, 1023])),
+            2
+        );
+        assert_eq!(
+            toroidal_distance_squared(&torus, &origin, &TorusPoint::new([512, 512])),
+            2 * 512 * 512
+        );
+    }
+
+    #[test]
+    fn test_toroidal_distance_squared_on_non_square_torus() {
+        let torus = Torus::new([100, 7]).unwrap();
+        assert_eq!(
+            toroidal_distance_squared(&torus, &TorusPoint::new([95, 6]), &TorusPoint::new([5, 0])),
+            100 + 1
+        );
+        assert_eq!(
+            toroidal_distance_squared(&torus, &TorusPoint::ORIGIN, &TorusPoint::new([50, 3])),
+            2500 + 9
+        );
+    }
+
+    #[test]
+    fn test_toroidal_distance_squared_in_sixteen_dimensions() {
+        let torus = Torus::<16>::FULL;
+        let far = TorusPoint::new([1 << 31; 16]);
+        // sixteen half turns would overflow a u64
+        assert_eq!(
+            toroidal_distance_squared(&torus, &TorusPoint::ORIGIN, &far),
+            16 << 62
+        );
+
+        let mut near = TorusPoint::ORIGIN;
+        near[15] = u32::MAX;
         assert_eq!(
-            toroidal_distance_squared(10, 20, std::u32::MAX - 10, std::u32::MAX - 20),
-            2000
+            toroidal_distance_squared(&torus, &TorusPoint::ORIGIN, &near),
+            1
         );
     }
 }
@@ -0,0 +1,317 @@
+use crate::toroidal_metric::{Euclidean, ToroidalMetric};
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+// Buckets points into a grid of cells laid over the torus. Cells wrap with the torus, so a query
+// near the seam also visits the cells on the far side of it.
+pub struct ToroidalGridIndex<const D: usize = 2> {
+    torus: Torus<D>,
+    cells_per_axis: [u64; D],
+    cells: HashMap<[u32; D], HashSet<Uuid>>,
+    points: HashMap<Uuid, TorusPoint<D>>,
+}
+
+impl<const D: usize> ToroidalGridIndex<D> {
+    pub fn new(torus: Torus<D>, cells_per_axis: u32) -> Self {
+        ToroidalGridIndex {
+            torus,
+            cells_per_axis: torus
+                .sizes()
+                .map(|size| size.min(cells_per_axis.max(1) as u64)),
+            cells: HashMap::new(),
+            points: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&TorusPoint<D>> {
+        self.points.get(id)
+    }
+
+    // Inserts `id` or moves it if it is already indexed.
+    pub fn insert(&mut self, id: Uuid, point: TorusPoint<D>) {
+        let point = self.torus.wrap(&point);
+        self.remove(&id);
+        self.cells
+            .entry(self.cell_of(&point))
+            .or_default()
+            .insert(id);
+        self.points.insert(id, point);
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<TorusPoint<D>> {
+        let point = self.points.remove(id)?;
+        let cell = self.cell_of(&point);
+        if let Some(ids) = self.cells.get_mut(&cell) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+        Some(point)
+    }
+
+    // Every indexed id no further than sqrt(radius_squared) from `point`, nearest first.
+    pub fn within_radius(&self, point: &TorusPoint<D>, radius_squared: u128) -> Vec<(Uuid, u128)> {
+        self.within_radius_by(&Euclidean, point, radius_squared)
+    }
+
+    // Every indexed id at most `max_distance` from `point` under `metric`, nearest first.
+    pub fn within_radius_by<M: ToroidalMetric<D>>(
+        &self,
+        metric: &M,
+        point: &TorusPoint<D>,
+        max_distance: u128,
+    ) -> Vec<(Uuid, u128)> {
+        let point = self.torus.wrap(point);
+        let mut found: Vec<(Uuid, u128)> = self
+            .candidate_cells(metric, &point, max_distance)
+            .into_iter()
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(|id| (*id, metric.distance(&self.torus, &point, &self.points[id])))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .collect();
+        found.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        found
+    }
+
+    // The k indexed ids nearest to `point`, nearest first, with squared distances.
+    pub fn nearest(&self, point: &TorusPoint<D>, k: usize) -> Vec<(Uuid, u128)> {
+        self.nearest_by(&Euclidean, point, k)
+    }
+
+    // The k indexed ids nearest to `point` under `metric`. Searches a growing distance, so only
+    // the cells around the point are visited while they still hold enough candidates.
+    pub fn nearest_by<M: ToroidalMetric<D>>(
+        &self,
+        metric: &M,
+        point: &TorusPoint<D>,
+        k: usize,
+    ) -> Vec<(Uuid, u128)> {
+        if k == 0 || self.points.is_empty() {
+            return Vec::new();
+        }
+
+        let diameter = metric.diameter(&self.torus);
+        // start at about one cell away along the axis where that is shortest
+        let widths = self.cell_widths();
+        let mut max_distance = (0..D)
+            .map(|axis| {
+                let mut offset = [0i64; D];
+                offset[axis] = widths[axis] as i64;
+                metric.distance_of_offset(&offset)
+            })
+            .min()
+            .unwrap_or(1)
+            .max(1);
+
+        loop {
+            let mut found = self.within_radius_by(metric, point, max_distance);
+            if found.len() >= k || max_distance >= diameter {
+                found.truncate(k);
+                return found;
+            }
+            max_distance = max_distance.saturating_mul(4).min(diameter);
+        }
+    }
+
+    fn cell_widths(&self) -> [u128; D] {
+        std::array::from_fn(|axis| {
+            (self.torus.sizes()[axis] as u128).div_ceil(self.cells_per_axis[axis] as u128)
+        })
+    }
+
+    fn cell_of(&self, point: &TorusPoint<D>) -> [u32; D] {
+        std::array::from_fn(|axis| {
+            (point[axis] as u128 * self.cells_per_axis[axis] as u128
+                / self.torus.sizes()[axis] as u128) as u32
+        })
+    }
+
+    // The cells overlapping the box around `point` that encloses every point within
+    // `max_distance`, or every occupied cell when that box would hold more cells than are occupied.
+    fn candidate_cells<M: ToroidalMetric<D>>(
+        &self,
+        metric: &M,
+        point: &TorusPoint<D>,
+        max_distance: u128,
+    ) -> Vec<[u32; D]> {
+        let center = self.cell_of(point);
+        let widths = self.cell_widths();
+
+        let mut axis_cells: Vec<Vec<u32>> = Vec::with_capacity(D);
+        let mut total: usize = 1;
+        for axis in 0..D {
+            let count = self.cells_per_axis[axis];
+            let reach = metric
+                .axis_reach(axis, max_distance)
+                .saturating_add(1)
+                .div_ceil(widths[axis]);
+            let cells: Vec<u32> = if reach.saturating_mul(2).saturating_add(1) >= count as u128 {
+                (0..count as u32).collect()
+            } else {
+                (-(reach as i64)..=reach as i64)
+                    .map(|step| (center[axis] as i64 + step).rem_euclid(count as i64) as u32)
+                    .collect()
+            };
+            total = total.saturating_mul(cells.len());
+            axis_cells.push(cells);
+        }
+
+        if total > self.cells.len() {
+            return self.cells.keys().copied().collect();
+        }
+
+        let mut cells = vec![[0u32; D]];
+        for (axis, choices) in axis_cells.iter().enumerate() {
+            cells = cells
+                .into_iter()
+                .flat_map(|cell| {
+                    choices.iter().map(move |&choice| {
+                        let mut next = cell;
+                        next[axis] = choice;
+                        next
+                    })
+                })
+                .collect();
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toroidal_distance_squared::toroidal_distance_squared;
+    use crate::toroidal_metric::{Chebyshev, Manhattan, WeightedAxes};
+
+    fn ids(found: &[(Uuid, u128)]) -> Vec<Uuid> {
+        found.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn test_grid_index_insert_move_and_remove() {
+        let mut index = ToroidalGridIndex::new(Torus::new([1024, 1024]).unwrap(), 16);
+        let id = Uuid::new_v4();
+
+        index.insert(id, TorusPoint::new([10, 10]));
+        index.insert(id, TorusPoint::new([1000, 500]));
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get(&id), Some(&TorusPoint::new([1000, 500])));
+        assert_eq!(
+            ids(&index.within_radius(&TorusPoint::new([10, 10]), 100)),
+            Vec::<Uuid>::new()
+        );
+
+        assert_eq!(index.remove(&id), Some(TorusPoint::new([1000, 500])));
+        assert!(index.is_empty());
+        assert!(index.cells.is_empty());
+    }
+
+    #[test]
+    fn test_grid_index_radius_query_across_seam() {
+        let mut index = ToroidalGridIndex::new(Torus::new([1024, 1024]).unwrap(), 16);
+        let (near, far) = (Uuid::new_v4(), Uuid::new_v4());
+        index.insert(near, TorusPoint::new([1020, 3]));
+        index.insert(far, TorusPoint::new([512, 512]));
+
+        let found = index.within_radius(&TorusPoint::new([2, 1020]), 100);
+        assert_eq!(found, vec![(near, 6 * 6 + 7 * 7)]);
+    }
+
+    #[test]
+    fn test_grid_index_nearest_matches_brute_force() {
+        let torus = Torus::new([1000, 600]).unwrap();
+        let mut index = ToroidalGridIndex::new(torus, 8);
+        let mut points = Vec::new();
+        for i in 0..300u32 {
+            let id = Uuid::new_v4();
+            let point =
+                TorusPoint::new([i.wrapping_mul(7_919) % 1000, i.wrapping_mul(104_729) % 600]);
+            index.insert(id, point);
+            points.push((id, point));
+        }
+
+        for query in [[0, 0], [999, 599], [500, 300], [3, 597]] {
+            let query = TorusPoint::new(query);
+            let mut expected: Vec<(Uuid, u128)> = points
+                .iter()
+                .map(|(id, point)| (*id, toroidal_distance_squared(&torus, &query, point)))
+                .collect();
+            expected.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+            expected.truncate(5);
+
+            assert_eq!(index.nearest(&query, 5), expected);
+        }
+    }
+
+    fn assert_nearest_matches_brute_force<M: ToroidalMetric<2>>(metric: &M) {
+        let torus = Torus::new([1000, 600]).unwrap();
+        let mut index = ToroidalGridIndex::new(torus, 8);
+        let mut points = Vec::new();
+        for i in 0..200u32 {
+            let id = Uuid::new_v4();
+            let point =
+                TorusPoint::new([i.wrapping_mul(7_919) % 1000, i.wrapping_mul(104_729) % 600]);
+            index.insert(id, point);
+            points.push((id, point));
+        }
+
+        for query in [[0, 0], [999, 599], [500, 300]] {
+            let query = TorusPoint::new(query);
+            let mut expected: Vec<(Uuid, u128)> = points
+                .iter()
+                .map(|(id, point)| (*id, metric.distance(&torus, &query, point)))
+                .collect();
+            expected.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+            expected.truncate(7);
+
+            assert_eq!(index.nearest_by(metric, &query, 7), expected);
+        }
+    }
+
+    #[test]
+    fn test_grid_index_nearest_under_other_metrics() {
+        assert_nearest_matches_brute_force(&Manhattan);
+        assert_nearest_matches_brute_force(&Chebyshev);
+        // with the x axis ignored the search has to span the whole of it
+        assert_nearest_matches_brute_force(&WeightedAxes::new([0, 3]));
+    }
+
+    #[test]
+    fn test_grid_index_nearest_with_fewer_points_than_k() {
+        let mut index = ToroidalGridIndex::new(Torus::FULL, 64);
+        let id = Uuid::new_v4();
+        index.insert(id, TorusPoint::new([u32::MAX, 0]));
+
+        assert_eq!(
+            index.nearest(&TorusPoint::new([1 << 31, 1 << 31]), 3),
+            vec![(id, (1u128 << 62) * 2 - (1 << 32) + 1)]
+        );
+        assert!(index.nearest(&TorusPoint::ORIGIN, 0).is_empty());
+    }
+
+    #[test]
+    fn test_grid_index_in_six_dimensions() {
+        let mut index = ToroidalGridIndex::<6>::new(Torus::FULL, 32);
+        let (near, far) = (Uuid::new_v4(), Uuid::new_v4());
+        index.insert(near, TorusPoint::new([u32::MAX, 1, 0, 0, 0, u32::MAX]));
+        index.insert(far, TorusPoint::new([1 << 31; 6]));
+
+        assert_eq!(index.nearest(&TorusPoint::ORIGIN, 1), vec![(near, 3)]);
+        assert_eq!(
+            ids(&index.within_radius(&TorusPoint::ORIGIN, 2)),
+            Vec::<Uuid>::new()
+        );
+    }
+}
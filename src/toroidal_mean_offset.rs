@@ -0,0 +1,106 @@
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+
+// Average of the shortest signed offsets from `origin` to each point, so it points at the local
+// centroid without ever averaging across the wrap seam.
+pub fn toroidal_mean_offset<const D: usize>(
+    torus: &Torus<D>,
+    origin: &TorusPoint<D>,
+    points: &[TorusPoint<D>],
+) -> Option<[i64; D]> {
+    if points.is_empty() {
+        return None;
+    }
+
+    let mut sums = [0i128; D];
+    for point in points {
+        for (sum, delta) in sums.iter_mut().zip(torus.offset(origin, point)) {
+            *sum += delta as i128;
+        }
+    }
+
+    // each mean is a mean of offsets no longer than half an axis, so it fits back into an i64
+    let count = points.len() as i128;
+    Some(sums.map(|sum| (sum / count) as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(coordinates: &[[u32; 2]]) -> Vec<TorusPoint> {
+        coordinates.iter().copied().map(TorusPoint::new).collect()
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_empty() {
+        assert_eq!(
+            toroidal_mean_offset(&Torus::FULL, &TorusPoint::ORIGIN, &points(&[])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_single_point() {
+        assert_eq!(
+            toroidal_mean_offset(
+                &Torus::FULL,
+                &TorusPoint::new([10, 20]),
+                &points(&[[13, 16]])
+            ),
+            Some([3, -4])
+        );
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_across_seam() {
+        // u32::MAX is one step behind 0, so the offset must not jump across the whole axis
+        assert_eq!(
+            toroidal_mean_offset(
+                &Torus::FULL,
+                &TorusPoint::new([1, u32::MAX]),
+                &points(&[[u32::MAX, 1]])
+            ),
+            Some([-2, 2])
+        );
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_averages_both_sides_of_seam() {
+        let points = points(&[[u32::MAX - 9, 0], [10, 0]]);
+        assert_eq!(
+            toroidal_mean_offset(&Torus::FULL, &TorusPoint::ORIGIN, &points),
+            Some([0, 0])
+        );
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_many_far_points() {
+        // every offset is a half turn, which a u32 or i32 sum could not hold
+        let points = vec![TorusPoint::new([1u32 << 31, 1u32 << 31]); 10_000];
+        assert_eq!(
+            toroidal_mean_offset(&Torus::FULL, &TorusPoint::ORIGIN, &points),
+            Some([1 << 31, 1 << 31])
+        );
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_on_small_torus() {
+        let torus = Torus::new([1024, 10]).unwrap();
+        let points = points(&[[1020, 9], [1022, 8]]);
+        assert_eq!(
+            toroidal_mean_offset(&torus, &TorusPoint::new([2, 1]), &points),
+            Some([-5, -2])
+        );
+    }
+
+    #[test]
+    fn test_toroidal_mean_offset_in_four_dimensions() {
+        let torus = Torus::new([8, 8, 8, 8]).unwrap();
+        let points = [TorusPoint::new([7, 1, 2, 4]), TorusPoint::new([1, 1, 6, 4])];
+        assert_eq!(
+            toroidal_mean_offset(&torus, &TorusPoint::ORIGIN, &points),
+            Some([0, 1, 0, 4])
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::centroid_strategy::{centroid_from_snapshot, window_after_push, CentroidStrategy};
+use crate::error::Error;
+use crate::fixed_circular_buffer::FixedCircularBuffer;
+use crate::process_event::EventInfo;
+use crate::snapshot::SnapshotError;
+use crate::toroidal_distance_squared::toroidal_distance_squared;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// The flee point in the window with the least weighted sum of squared toroidal distances to all
+// the others: the Fréchet mean restricted to points that actually occurred, so the centroid is
+// always somewhere an event fled to rather than in empty space between clusters. Ties go to the
+// newer point. It costs O(n^2) per admit in the window size and is recomputed after any move has
+// been applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ToroidalMedoid<const D: usize = 2> {
+    torus: Torus<D>,
+    centroid: Option<TorusPoint<D>>,
+}
+
+impl<const D: usize> ToroidalMedoid<D> {
+    pub fn new(torus: Torus<D>) -> Self {
+        ToroidalMedoid {
+            torus,
+            centroid: None,
+        }
+    }
+}
+
+impl<const D: usize> CentroidStrategy<D> for ToroidalMedoid<D> {
+    const NAME: &'static str = "medoid";
+
+    fn torus(&self) -> &Torus<D> {
+        &self.torus
+    }
+
+    fn admit(
+        &mut self,
+        event_map: &HashMap<Uuid, EventInfo<D>>,
+        event_buffer: &FixedCircularBuffer<Uuid>,
+        latest_uuid: &Uuid,
+    ) -> Result<(), Error> {
+        let window = window_after_push(event_map, event_buffer, latest_uuid)?;
+
+        let mut best: Option<(u128, TorusPoint<D>)> = None;
+        for candidate in &window {
+            let mut cost: u128 = 0;
+            for other in &window {
+                let distance = toroidal_distance_squared(&self.torus, &candidate.flee, &other.flee);
+                cost = distance
+                    .checked_mul(other.weight as u128)
+                    .and_then(|weighted| cost.checked_add(weighted))
+                    .ok_or(Error::Overflow)?;
+            }
+            if best.is_none_or(|(lowest, _)| cost < lowest) {
+                best = Some((cost, candidate.flee));
+            }
+        }
+
+        self.centroid = best.map(|(_, point)| point);
+        Ok(())
+    }
+
+    fn moved(&mut self, _: &EventInfo<D>, _: &EventInfo<D>, _: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn centroid(&self) -> Option<TorusPoint<D>> {
+        self.centroid
+    }
+
+    fn to_snapshot(&self) -> Value {
+        json!({ "centroid": self.centroid.map(|point| point.coordinates.to_vec()) })
+    }
+
+    fn from_snapshot(torus: Torus<D>, snapshot: &Value) -> Result<Self, SnapshotError> {
+        Ok(ToroidalMedoid {
+            torus,
+            centroid: centroid_from_snapshot(snapshot)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admit_window(medoid: &mut ToroidalMedoid, flees: &[[u32; 2]]) {
+        let ids: Vec<Uuid> = flees.iter().map(|_| Uuid::new_v4()).collect();
+        let map: HashMap<Uuid, EventInfo> = ids
+            .iter()
+            .zip(flees)
+            .map(|(id, flee)| {
+                (
+                    *id,
+                    EventInfo::new(TorusPoint::ORIGIN, TorusPoint::new(*flee)),
+                )
+            })
+            .collect();
+        let mut buffer = FixedCircularBuffer::new(flees.len());
+        for id in &ids[..ids.len() - 1] {
+            buffer.push_front(*id);
+        }
+        medoid.admit(&map, &buffer, &ids[ids.len() - 1]).unwrap();
+    }
+
+    #[test]
+    fn test_medoid_stays_on_a_window_point_across_seam() {
+        let torus = Torus::new([1000, 1000]).unwrap();
+        let mut medoid = ToroidalMedoid::new(torus);
+        assert_eq!(medoid.centroid(), None);
+
+        // costs are 245299 for 995, 248117 for 2 and 240389 for 10
+        admit_window(&mut medoid, &[[995, 0], [2, 0], [10, 0], [500, 0]]);
+        assert_eq!(medoid.centroid(), Some(TorusPoint::new([10, 0])));
+    }
+
+    #[test]
+    fn test_medoid_tie_goes_to_newest_and_snapshot_round_trips() {
+        let torus = Torus::new([1000, 1000]).unwrap();
+        let mut medoid = ToroidalMedoid::new(torus);
+
+        // the last flee is the latest event, so it is first in the window
+        admit_window(&mut medoid, &[[100, 0], [200, 0]]);
+        assert_eq!(medoid.centroid(), Some(TorusPoint::new([200, 0])));
+        assert_eq!(
+            ToroidalMedoid::from_snapshot(torus, &medoid.to_snapshot()).unwrap(),
+            medoid
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::furthest_coordinates_toroidal::furthest_coordinates_toroidal;
+use crate::torus::Torus;
+use crate::torus_point::TorusPoint;
+
+// How far apart two points are, from the shortest offset between them along each axis. Distances
+// are integers in the metric's own units, so `Euclidean` measures squared distance to stay exact.
+// Every metric here grows with the size of each axis offset, which the grid index relies on to
+// bound its search and which makes the antipode the furthest point from any other.
+pub trait ToroidalMetric<const D: usize> {
+    fn distance_of_offset(&self, offset: &[i64; D]) -> u128;
+
+    // The largest offset along `axis` that a point at most `distance` away can have.
+    fn axis_reach(&self, axis: usize, distance: u128) -> u128;
+
+    fn distance(&self, torus: &Torus<D>, a: &TorusPoint<D>, b: &TorusPoint<D>) -> u128 {
+        self.distance_of_offset(&torus.offset(a, b))
+    }
+
+    // A point as far from `point` as any on the torus.
+    fn furthest(&self, torus: &Torus<D>, point: &TorusPoint<D>) -> TorusPoint<D> {
+        furthest_coordinates_toroidal(torus, point)
+    }
+
+    // The largest distance between two points of the torus.
+    fn diameter(&self, torus: &Torus<D>) -> u128 {
+        let origin = TorusPoint::ORIGIN;
+        self.distance(torus, &origin, &self.furthest(torus, &origin))
+    }
+}
+
+// Squared straight-line distance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Euclidean;
+
+// The sum of the offsets along each axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Manhattan;
+
+// The largest offset along any one axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Chebyshev;
+
+// Squared distance with each axis scaled by its weight, so a weight of zero ignores that axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeightedAxes<const D: usize = 2> {
+    pub weights: [u32; D],
+}
+
+impl<const D: usize> WeightedAxes<D> {
+    pub fn new(weights: [u32; D]) -> Self {
+        WeightedAxes { weights }
+    }
+}
+
+// each offset is at most 2^31, so its square fits a u128 many times over
+fn squared(delta: i64) -> u128 {
+    (delta.unsigned_abs() as u128).pow(2)
+}
+
+impl<const D: usize> ToroidalMetric<D> for Euclidean {
+    fn distance_of_offset(&self, offset: &[i64; D]) -> u128 {
+        offset.iter().map(|delta| squared(*delta)).sum()
+    }
+
+    fn axis_reach(&self, _: usize, distance: u128) -> u128 {
+        distance.isqrt()
+    }
+}
+
+impl<const D: usize> ToroidalMetric<D> for Manhattan {
+    fn distance_of_offset(&self, offset: &[i64; D]) -> u128 {
+        offset
+            .iter()
+            .map(|delta| delta.unsigned_abs() as u128)
+            .sum()
+    }
+
+    fn axis_reach(&self, _: usize, distance: u128) -> u128 {
+        distance
+    }
+}
+
+impl<const D: usize> ToroidalMetric<D> for Chebyshev {
+    fn distance_of_offset(&self, offset: &[i64; D]) -> u128 {
+        offset
+            .iter()
+            .map(|delta| delta.unsigned_abs() as u128)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn axis_reach(&self, _: usize, distance: u128) -> u128 {
+        distance
+    }
+}
+
+impl<const D: usize> ToroidalMetric<D> for WeightedAxes<D> {
+    fn distance_of_offset(&self, offset: &[i64; D]) -> u128 {
+        offset
+            .iter()
+            .zip(self.weights)
+            .map(|(delta, weight)| weight as u128 * squared(*delta))
+            .sum()
+    }
+
+    fn axis_reach(&self, axis: usize, distance: u128) -> u128 {
+        match self.weights[axis] {
+            0 => u128::MAX,
+            weight => (distance / weight as u128).isqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_measure_across_seam() {
+        let torus = Torus::new([100, 7]).unwrap();
+        let (a, b) = (TorusPoint::new([95, 6]), TorusPoint::new([5, 1]));
+
+        // the shortest offsets are 10 and 2
+        assert_eq!(Euclidean.distance(&torus, &a, &b), 104);
+        assert_eq!(Manhattan.distance(&torus, &a, &b), 12);
+        assert_eq!(Chebyshev.distance(&torus, &a, &b), 10);
+        assert_eq!(WeightedAxes::new([1, 25]).distance(&torus, &a, &b), 200);
+        assert_eq!(WeightedAxes::new([0, 1]).distance(&torus, &a, &b), 4);
+    }
+
+    #[test]
+    fn test_metric_diameters() {
+        let torus = Torus::new([100, 7]).unwrap();
+        assert_eq!(Euclidean.diameter(&torus), 50 * 50 + 3 * 3);
+        assert_eq!(Manhattan.diameter(&torus), 53);
+        assert_eq!(Chebyshev.diameter(&torus), 50);
+        assert_eq!(WeightedAxes::new([0, 2]).diameter(&torus), 18);
+
+        let full = Torus::<16>::FULL;
+        assert_eq!(Manhattan.diameter(&full), 16 << 31);
+        assert_eq!(Euclidean.diameter(&full), 16 << 62);
+    }
+
+    #[test]
+    fn test_axis_reach_bounds_every_point_within_distance() {
+        let torus = Torus::new([40, 40]).unwrap();
+        let metrics: [&dyn ToroidalMetric<2>; 4] = [
+            &Euclidean,
+            &Manhattan,
+            &Chebyshev,
+            &WeightedAxes::new([3, 0]),
+        ];
+        for metric in metrics {
+            for distance in [0, 1, 5, 50, 400] {
+                for x in 0..40 {
+                    for y in 0..40 {
+                        let offset = torus.offset(&TorusPoint::ORIGIN, &TorusPoint::new([x, y]));
+                        if metric.distance_of_offset(&offset) > distance {
+                            continue;
+                        }
+                        for (axis, delta) in offset.iter().enumerate() {
+                            assert!(
+                                delta.unsigned_abs() as u128 <= metric.axis_reach(axis, distance)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,179 +1,209 @@
-use crate::{fixed_circular_buffer::FixedCircularBuffer, process_event::EventInfo};
+use crate::{
+    circular_mean_accumulator::CircularMeanAccumulator, error::Error,
+    fixed_circular_buffer::FixedCircularBuffer, process_event::EventInfo, torus_point::TorusPoint,
+};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-// TODO: make toroidal wrapping work
-pub fn toroidal_rolling_flee_average(
-    event_map: &HashMap<Uuid, EventInfo>,
+// Folds `latest_uuid` into the flee sums as if it were pushed onto the front of `event_buffer`,
+// subtracting the oldest id when that push would evict it, then returns the new circular mean.
+// Call it before the push so the oldest id is still at the back. On error `flee_sums` is unchanged.
+pub fn toroidal_rolling_flee_average<const D: usize>(
+    event_map: &HashMap<Uuid, EventInfo<D>>,
     event_buffer: &FixedCircularBuffer<Uuid>,
-    previous_average: &Option<(u32, u32)>,
-) -> Option<(u32, u32)> {
-    let buffer_len = event_buffer.len() as u32;
-    let buffer_capacity = event_buffer.capacity as u32;
-
-    if buffer_len == 0 {
-        return None;
+    flee_sums: &mut CircularMeanAccumulator<D>,
+    latest_uuid: &Uuid,
+) -> Result<TorusPoint<D>, Error> {
+    if event_buffer.capacity() == 0 {
+        return Err(Error::ZeroCapacity);
     }
 
-    let buffer_previous_length = buffer_len - 1;
-
-    if let Some(latest_uuid) = event_buffer.front() {
-        let latest_info = event_map.get(&latest_uuid);
-
-        if let Some(latest) = latest_info {
-            if let Some(prev) = previous_average {
-                if buffer_capacity == buffer_len {
-                    if let Some(oldest_uuid) = event_buffer.back() {
-                        let oldest_info = event_map.get(&oldest_uuid);
-
-                        if let Some(info) = oldest_info {
-                            let x = ((prev.0 * buffer_previous_length) - info.flee_x
-                                + latest.flee_x)
-                                / buffer_previous_length;
-                            let y = ((prev.1 * buffer_previous_length) - info.flee_y
-                                + latest.flee_y)
-                                / buffer_previous_length;
-
-                            return Some((x, y));
-                        } else {
-                            return None;
-                        }
-                    }
-                } else {
-                    let x = ((prev.0 * buffer_previous_length) + latest.flee_x) / buffer_len;
-                    let y = ((prev.1 * buffer_previous_length) + latest.flee_y) / buffer_len;
-
-                    return Some((x, y));
-                }
-            } else {
-                return Some((latest.flee_x, latest.flee_y));
-            }
+    let latest = event_map
+        .get(latest_uuid)
+        .ok_or(Error::MissingId(*latest_uuid))?;
+
+    let mut sums = *flee_sums;
+    if event_buffer.is_full() {
+        if let Some(oldest_uuid) = event_buffer.back() {
+            let oldest = event_map
+                .get(oldest_uuid)
+                .ok_or(Error::MissingId(*oldest_uuid))?;
+            sums.remove_weighted(&oldest.flee, oldest.weight)?;
         }
     }
 
-    None
+    sums.add_weighted(&latest.flee, latest.weight)?;
+    *flee_sums = sums;
+    flee_sums.mean().ok_or(Error::EmptyWindow)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn flee_info(flee_x: u32, flee_y: u32) -> EventInfo {
+        EventInfo::new(TorusPoint::new([0, 0]), TorusPoint::new([flee_x, flee_y]))
+    }
+
     #[test]
     fn test_toroidal_rolling_average_with_empty_input() {
         let empty_map = HashMap::new();
         let empty_buffer = FixedCircularBuffer::new(0);
-        let previous_rolling_average = None;
+        let mut flee_sums = CircularMeanAccumulator::new();
         assert_eq!(
-            toroidal_rolling_flee_average(&empty_map, &empty_buffer, &previous_rolling_average),
-            None
+            toroidal_rolling_flee_average(&empty_map, &empty_buffer, &mut flee_sums, &Uuid::nil()),
+            Err(Error::ZeroCapacity)
         );
     }
 
     #[test]
-    fn test_toroidal_rolling_average_with_one_item_in_buffer() {
+    fn test_toroidal_rolling_average_with_missing_id() {
         let mut map = HashMap::new();
-        let item_uuid = Uuid::new_v4();
-        let event_info = EventInfo {
-            flee_x: 3,
-            flee_y: 7,
-            follow_x: u32::MAX,
-            follow_y: u32::MAX,
-        };
-        map.insert(item_uuid, event_info);
+        let buffer = FixedCircularBuffer::new(2);
+        let mut flee_sums = CircularMeanAccumulator::new();
+        let missing_uuid = Uuid::new_v4();
 
-        let mut buffer = FixedCircularBuffer::new(1);
-        buffer.push_front(item_uuid);
-
-        let previous_rolling_average = None;
+        assert_eq!(
+            toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &missing_uuid),
+            Err(Error::MissingId(missing_uuid))
+        );
 
+        // an evicted id that has vanished from the map is reported too
+        let mut buffer = FixedCircularBuffer::new(1);
+        buffer.push_front(missing_uuid);
+        let item_uuid = Uuid::new_v4();
+        map.insert(item_uuid, flee_info(1, 2));
         assert_eq!(
-            toroidal_rolling_flee_average(&map, &buffer, &previous_rolling_average),
-            Some((3, 7))
+            toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid),
+            Err(Error::MissingId(missing_uuid))
         );
+        assert_eq!(flee_sums.count, 0);
     }
 
     #[test]
-    fn test_toroidal_rolling_average_with_previous_rolling_average_and_one_item_in_buffer() {
+    fn test_toroidal_rolling_average_with_one_item_in_buffer() {
         let mut map = HashMap::new();
         let item_uuid = Uuid::new_v4();
-        let event_info = EventInfo {
-            flee_x: 1,
-            flee_y: 2,
-            follow_x: 0,
-            follow_y: 0,
-        };
-        map.insert(item_uuid, event_info);
-
-        let mut buffer = FixedCircularBuffer::new(3);
-        buffer.push_front(item_uuid);
+        map.insert(item_uuid, flee_info(3, 7));
 
-        // this previous rolling average is ignored because it should be None in reality
-        let previous_rolling_average = Some((3, 4));
+        let buffer = FixedCircularBuffer::new(1);
+        let mut flee_sums = CircularMeanAccumulator::new();
 
         assert_eq!(
-            toroidal_rolling_flee_average(&map, &buffer, &previous_rolling_average),
-            Some((1, 2))
+            toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid),
+            Ok(TorusPoint::new([3, 7]))
         );
+        assert_eq!(flee_sums.count, 1);
     }
 
     #[test]
-    fn test_toroidal_rolling_average_with_previous_rolling_average_and_three_items_in_buffer() {
+    fn test_toroidal_rolling_average_wraps_across_seam() {
         let mut map = HashMap::new();
         let item_uuid_1 = Uuid::new_v4();
         let item_uuid_2 = Uuid::new_v4();
-        let item_uuid_3 = Uuid::new_v4();
-        let event_info_3 = EventInfo {
-            flee_x: 9,
-            flee_y: 12,
-            follow_x: 0,
-            follow_y: 0,
-        };
-        map.insert(item_uuid_3, event_info_3);
+        map.insert(item_uuid_1, flee_info(1, u32::MAX - 2));
+        map.insert(item_uuid_2, flee_info(u32::MAX, 5));
 
-        let mut buffer = FixedCircularBuffer::new(4);
-        buffer.push_front(item_uuid_1);
-        buffer.push_front(item_uuid_2);
-        buffer.push_front(item_uuid_3);
+        let mut buffer = FixedCircularBuffer::new(3);
+        let mut flee_sums = CircularMeanAccumulator::new();
 
-        let previous_rolling_average = Some((3, 6));
+        toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid_1).unwrap();
+        buffer.push_front(item_uuid_1);
 
+        // a plain average would land in the middle of the axis
         assert_eq!(
-            toroidal_rolling_flee_average(&map, &buffer, &previous_rolling_average),
-            Some((5, 8))
+            toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid_2),
+            Ok(TorusPoint::new([0, 1]))
         );
     }
 
+    #[test]
+    fn test_toroidal_rolling_average_with_three_items_in_buffer() {
+        let mut map = HashMap::new();
+        let item_uuids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        map.insert(item_uuids[0], flee_info(3, 6));
+        map.insert(item_uuids[1], flee_info(5, 8));
+        map.insert(item_uuids[2], flee_info(7, 10));
+
+        let mut buffer = FixedCircularBuffer::new(4);
+        let mut flee_sums = CircularMeanAccumulator::new();
+        let mut average = Err(Error::EmptyWindow);
+        for uuid in &item_uuids {
+            average = toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, uuid);
+            buffer.push_front(*uuid);
+        }
+
+        assert_eq!(average, Ok(TorusPoint::new([5, 8])));
+        assert_eq!(flee_sums.count, 3);
+    }
+
     #[test]
     fn test_toroidal_rolling_average_x_with_capacity_full() {
         let mut map = HashMap::new();
         let item_uuid_1 = Uuid::parse_str("849761d6-e58f-423d-82fb-69ac2889408e").unwrap();
-        let event_info_1 = EventInfo {
-            flee_x: 4,
-            flee_y: 0,
-            follow_x: 0,
-            follow_y: 0,
-        };
-        map.insert(item_uuid_1, event_info_1);
+        map.insert(item_uuid_1, flee_info(4, 0));
         let item_uuid_2 = Uuid::parse_str("7a2d65a7-b338-4f7f-891b-3c612ea36d73").unwrap();
+        map.insert(item_uuid_2, flee_info(8, 0));
         let item_uuid_3 = Uuid::parse_str("249e486c-e7f3-40c9-a33d-9159fcd1e5ca").unwrap();
-        let event_info_3 = EventInfo {
-            flee_x: 12,
-            flee_y: 0,
-            follow_x: 0,
-            follow_y: 0,
-        };
-        map.insert(item_uuid_3, event_info_3);
+        map.insert(item_uuid_3, flee_info(12, 0));
 
-        let mut buffer = FixedCircularBuffer::new(3);
-        buffer.push_front(item_uuid_1);
-        buffer.push_front(item_uuid_2);
-        buffer.push_front(item_uuid_3);
-
-        let previous_rolling_average = Some((3, 0));
+        let mut buffer = FixedCircularBuffer::new(2);
+        let mut flee_sums = CircularMeanAccumulator::new();
+        for uuid in [&item_uuid_1, &item_uuid_2] {
+            toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, uuid).unwrap();
+            buffer.push_front(*uuid);
+        }
 
+        // item 1 is evicted, leaving items 2 and 3
         assert_eq!(
-            toroidal_rolling_flee_average(&map, &buffer, &previous_rolling_average),
-            Some((7, 0))
+            toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid_3),
+            Ok(TorusPoint::new([10, 0]))
         );
+        assert_eq!(flee_sums.count, 2);
+    }
+
+    #[test]
+    fn test_toroidal_rolling_average_with_capacity_one_over_many_evictions() {
+        let mut map = HashMap::new();
+        let mut buffer = FixedCircularBuffer::new(1);
+        let mut flee_sums = CircularMeanAccumulator::new();
+
+        for i in 0..5_000u32 {
+            let item_uuid = Uuid::new_v4();
+            map.insert(item_uuid, flee_info(u32::MAX - i, u32::MAX / 2 + i));
+
+            assert_eq!(
+                toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid),
+                Ok(TorusPoint::new([u32::MAX - i, u32::MAX / 2 + i]))
+            );
+            buffer.push_front(item_uuid);
+            assert_eq!(flee_sums.count, 1);
+        }
+    }
+
+    #[test]
+    fn test_toroidal_rolling_average_matches_fresh_sums_after_many_evictions() {
+        let mut map = HashMap::new();
+        let mut buffer = FixedCircularBuffer::new(64);
+        let mut flee_sums = CircularMeanAccumulator::new();
+
+        let mut average = Err(Error::EmptyWindow);
+        for i in 0..10_000u32 {
+            let item_uuid = Uuid::new_v4();
+            let flee_x = u32::MAX - i.wrapping_mul(2_654_435_761) % 1024;
+            let flee_y = i.wrapping_mul(40_503) | (1 << 31);
+            map.insert(item_uuid, flee_info(flee_x, flee_y));
+
+            average = toroidal_rolling_flee_average(&map, &buffer, &mut flee_sums, &item_uuid);
+            buffer.push_front(item_uuid);
+        }
+
+        let mut fresh = CircularMeanAccumulator::new();
+        for uuid in &buffer {
+            let info = map.get(uuid).unwrap();
+            fresh.add(&info.flee).unwrap();
+        }
+        assert_eq!(flee_sums, fresh);
+        assert_eq!(average.ok(), fresh.mean());
     }
 }
@@ -0,0 +1,152 @@
+use crate::error::Error;
+use crate::torus_point::TorusPoint;
+
+const MAX_SIZE: u64 = 1 << 32; // every u32 coordinate is a distinct point
+
+// The world every coordinate lives in. Coordinates run from 0 to size - 1 on each axis and wrap
+// around, so the default 2^32 torus lines up exactly with u32 wrapping arithmetic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Torus<const D: usize = 2> {
+    sizes: [u64; D],
+}
+
+impl<const D: usize> Torus<D> {
+    pub const FULL: Torus<D> = Torus {
+        sizes: [MAX_SIZE; D],
+    };
+
+    pub fn new(sizes: [u64; D]) -> Result<Self, Error> {
+        if let Some(axis) = sizes.iter().position(|&size| size == 0 || size > MAX_SIZE) {
+            return Err(Error::InvalidTorus {
+                axis,
+                size: sizes[axis],
+            });
+        }
+        Ok(Torus { sizes })
+    }
+
+    pub fn sizes(&self) -> &[u64; D] {
+        &self.sizes
+    }
+
+    pub fn wrap(&self, point: &TorusPoint<D>) -> TorusPoint<D> {
+        self.shift(point, &[0; D])
+    }
+
+    // Shortest signed offsets from `from` to `to`; an exact half turn counts as positive.
+    pub fn offset(&self, from: &TorusPoint<D>, to: &TorusPoint<D>) -> [i64; D] {
+        std::array::from_fn(|axis| axis_offset(from[axis], to[axis], self.sizes[axis]))
+    }
+
+    pub fn shift(&self, point: &TorusPoint<D>, deltas: &[i64; D]) -> TorusPoint<D> {
+        TorusPoint::new(std::array::from_fn(|axis| {
+            axis_shift(point[axis], deltas[axis], self.sizes[axis])
+        }))
+    }
+}
+
+impl Torus {
+    pub fn width(&self) -> u64 {
+        self.sizes[0]
+    }
+
+    pub fn height(&self) -> u64 {
+        self.sizes[1]
+    }
+}
+
+impl<const D: usize> Default for Torus<D> {
+    fn default() -> Self {
+        Torus::FULL
+    }
+}
+
+fn axis_offset(from: u32, to: u32, size: u64) -> i64 {
+    let delta = (to as i64 - from as i64).rem_euclid(size as i64);
+    if delta > size as i64 / 2 {
+        delta - size as i64
+    } else {
+        delta
+    }
+}
+
+fn axis_shift(value: u32, delta: i64, size: u64) -> u32 {
+    (value as i64 + delta).rem_euclid(size as i64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torus_new_rejects_invalid_sizes() {
+        assert_eq!(
+            Torus::new([0, 10]),
+            Err(Error::InvalidTorus { axis: 0, size: 0 })
+        );
+        assert_eq!(
+            Torus::new([10, 20, MAX_SIZE + 1]),
+            Err(Error::InvalidTorus {
+                axis: 2,
+                size: MAX_SIZE + 1
+            })
+        );
+        assert_eq!(Torus::new([MAX_SIZE, MAX_SIZE]), Ok(Torus::FULL));
+        assert_eq!(Torus::<2>::default(), Torus::FULL);
+    }
+
+    #[test]
+    fn test_torus_offset_takes_short_way_around() {
+        let torus = Torus::new([1024, 10]).unwrap();
+        let (a, b) = (TorusPoint::new([1, 1]), TorusPoint::new([1023, 9]));
+        assert_eq!(torus.offset(&a, &b), [-2, -2]);
+        assert_eq!(torus.offset(&b, &a), [2, 2]);
+        // a half turn is positive on even axes
+        assert_eq!(
+            torus.offset(&TorusPoint::new([0, 0]), &TorusPoint::new([512, 5])),
+            [512, 5]
+        );
+    }
+
+    #[test]
+    fn test_torus_offset_on_full_torus() {
+        let torus = Torus::FULL;
+        assert_eq!(
+            torus.offset(
+                &TorusPoint::new([1, u32::MAX]),
+                &TorusPoint::new([u32::MAX, 1])
+            ),
+            [-2, 2]
+        );
+        assert_eq!(
+            torus.offset(&TorusPoint::new([0, 0]), &TorusPoint::new([1 << 31, 0])),
+            [1 << 31, 0]
+        );
+    }
+
+    #[test]
+    fn test_torus_shift_and_wrap() {
+        let torus = Torus::new([1024, 7]).unwrap();
+        assert_eq!(
+            torus.shift(&TorusPoint::new([1020, 2]), &[10, -5]),
+            TorusPoint::new([6, 4])
+        );
+        assert_eq!(
+            torus.wrap(&TorusPoint::new([1030, 7])),
+            TorusPoint::new([6, 0])
+        );
+        assert_eq!(
+            Torus::FULL.shift(&TorusPoint::new([u32::MAX, 0]), &[1, -1]),
+            TorusPoint::new([0, u32::MAX])
+        );
+    }
+
+    #[test]
+    fn test_torus_in_higher_dimensions() {
+        let torus = Torus::new([16, 8, 4, 1 << 32]).unwrap();
+        let from = TorusPoint::new([15, 0, 3, 0]);
+        let to = TorusPoint::new([1, 7, 1, u32::MAX]);
+        assert_eq!(torus.offset(&from, &to), [2, -1, 2, -1]);
+        assert_eq!(torus.shift(&from, &[2, -1, 2, -1]), to);
+    }
+}
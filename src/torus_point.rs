@@ -0,0 +1,68 @@
+use std::ops::{Index, IndexMut};
+
+// A point on a D-dimensional torus, one u32 coordinate per axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TorusPoint<const D: usize = 2> {
+    pub coordinates: [u32; D],
+}
+
+impl<const D: usize> TorusPoint<D> {
+    pub const ORIGIN: TorusPoint<D> = TorusPoint {
+        coordinates: [0; D],
+    };
+
+    pub fn new(coordinates: [u32; D]) -> Self {
+        TorusPoint { coordinates }
+    }
+}
+
+impl TorusPoint {
+    pub fn x(&self) -> u32 {
+        self.coordinates[0]
+    }
+
+    pub fn y(&self) -> u32 {
+        self.coordinates[1]
+    }
+}
+
+impl<const D: usize> Default for TorusPoint<D> {
+    fn default() -> Self {
+        TorusPoint::ORIGIN
+    }
+}
+
+impl<const D: usize> From<[u32; D]> for TorusPoint<D> {
+    fn from(coordinates: [u32; D]) -> Self {
+        TorusPoint::new(coordinates)
+    }
+}
+
+impl<const D: usize> Index<usize> for TorusPoint<D> {
+    type Output = u32;
+
+    fn index(&self, axis: usize) -> &u32 {
+        &self.coordinates[axis]
+    }
+}
+
+impl<const D: usize> IndexMut<usize> for TorusPoint<D> {
+    fn index_mut(&mut self, axis: usize) -> &mut u32 {
+        &mut self.coordinates[axis]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torus_point_accessors() {
+        let mut point = TorusPoint::new([3, 7]);
+        assert_eq!((point.x(), point.y()), (3, 7));
+
+        point[1] = 9;
+        assert_eq!(point, TorusPoint::from([3, 9]));
+        assert_eq!(TorusPoint::<5>::default(), TorusPoint::new([0; 5]));
+    }
+}
@@ -0,0 +1,395 @@
+use crate::error::Error;
+use crate::process_event::Event;
+use crate::snapshot::SnapshotError;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+const HEADER_LEN: usize = 8; // payload length then checksum, both u32 little endian
+const ID_ONLY_FORMAT: u8 = 1; // format byte, sequence number, id
+const RECORD_FORMAT: u8 = 2; // as above, then weight, timestamp flag and value, payload bytes
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(io::Error),
+    Snapshot(SnapshotError),
+    Engine(Error),
+    // a logged event the engine accepted before was rejected on replay
+    Replay(u64, Error),
+    // a complete record that fails its checksum or does not decode
+    Corrupt { offset: u64 },
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::Io(error) => write!(f, "event log I/O failed: {}", error),
+            LogError::Snapshot(error) => write!(f, "{}", error),
+            LogError::Engine(error) => write!(f, "{}", error),
+            LogError::Replay(sequence, error) => {
+                write!(f, "replaying logged event {} failed: {}", sequence, error)
+            }
+            LogError::Corrupt { offset } => write!(f, "event log is corrupt at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl From<io::Error> for LogError {
+    fn from(error: io::Error) -> Self {
+        LogError::Io(error)
+    }
+}
+
+impl From<SnapshotError> for LogError {
+    fn from(error: SnapshotError) -> Self {
+        LogError::Snapshot(error)
+    }
+}
+
+impl From<Error> for LogError {
+    fn from(error: Error) -> Self {
+        LogError::Engine(error)
+    }
+}
+
+// One logged event; sequence numbers grow by one per record and survive compaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    pub sequence: u64,
+    pub event: Event,
+}
+
+impl LogRecord {
+    fn encode(&self) -> Vec<u8> {
+        let event = &self.event;
+        let mut payload = Vec::with_capacity(38 + event.payload.len());
+        payload.push(RECORD_FORMAT);
+        payload.extend_from_slice(&self.sequence.to_le_bytes());
+        payload.extend_from_slice(event.id.as_bytes());
+        payload.extend_from_slice(&event.weight.to_le_bytes());
+        payload.push(event.timestamp.is_some() as u8);
+        payload.extend_from_slice(&event.timestamp.unwrap_or(0).to_le_bytes());
+        payload.extend_from_slice(&event.payload);
+
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+        record
+    }
+
+    fn decode(payload: &[u8]) -> Option<LogRecord> {
+        let format = *payload.first()?;
+        let sequence = u64::from_le_bytes(payload.get(1..9)?.try_into().ok()?);
+        let mut event = Event::new(Uuid::from_slice(payload.get(9..25)?).ok()?);
+
+        match format {
+            ID_ONLY_FORMAT if payload.len() == 25 => {}
+            RECORD_FORMAT => {
+                event.weight = u32::from_le_bytes(payload.get(25..29)?.try_into().ok()?);
+                let timestamp = u64::from_le_bytes(payload.get(30..38)?.try_into().ok()?);
+                event.timestamp = match payload.get(29)? {
+                    0 => None,
+                    1 => Some(timestamp),
+                    _ => return None,
+                };
+                event.payload = payload[38..].to_vec();
+            }
+            _ => return None,
+        }
+        Some(LogRecord { sequence, event })
+    }
+}
+
+// An append-only file of checksummed event records. Each append is synced before it returns, so
+// an acknowledged event survives a crash; a final record cut short by a crash mid-write is cut
+// off when the log is next opened. A complete record that is bad was not torn by a crash, and
+// opening the log fails rather than dropping an acknowledged event.
+pub struct WriteAheadLog {
+    file: File,
+    // where the file ends, and where the record before that end starts, if it may be discarded
+    end: u64,
+    last_start: Option<u64>,
+}
+
+impl WriteAheadLog {
+    // Opens or creates the log at `path` and returns it with every intact record, oldest first,
+    // or `LogError::Corrupt` if a bad record is complete.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, Vec<LogRecord>), LogError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        let mut last_start = None;
+        while let Some((record, length)) = read_record(&bytes[offset..]) {
+            records.push(record);
+            last_start = Some(offset as u64);
+            offset += length;
+        }
+
+        if offset < bytes.len() {
+            if !is_torn(&bytes[offset..]) {
+                return Err(LogError::Corrupt {
+                    offset: offset as u64,
+                });
+            }
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let log = WriteAheadLog {
+            file,
+            end: offset as u64,
+            last_start,
+        };
+        Ok((log, records))
+    }
+
+    pub fn append(&mut self, record: &LogRecord) -> Result<(), LogError> {
+        let bytes = record.encode();
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.last_start = Some(self.end);
+        self.end += bytes.len() as u64;
+        Ok(())
+    }
+
+    // Cuts off the record last appended, or the last one read back by `open`, for an event the
+    // engine rejected. Only that one record can be discarded.
+    pub fn discard_last(&mut self) -> Result<(), LogError> {
+        if let Some(start) = self.last_start.take() {
+            self.file.set_len(start)?;
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.sync_all()?;
+            self.end = start;
+        }
+        Ok(())
+    }
+
+    // Drops every record; call it once a snapshot covers them.
+    pub fn compact(&mut self) -> Result<(), LogError> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()?;
+        self.end = 0;
+        self.last_start = None;
+        Ok(())
+    }
+}
+
+// The first record in `bytes` and how many bytes it spans, unless it is torn or corrupt.
+fn read_record(bytes: &[u8]) -> Option<(LogRecord, usize)> {
+    let length = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+    let checksum = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+    let end = HEADER_LEN.checked_add(length)?;
+    let payload = bytes.get(HEADER_LEN..end)?;
+    if crc32(payload) != checksum {
+        return None;
+    }
+    LogRecord::decode(payload).map(|record| (record, end))
+}
+
+// Whether `tail`, which does not start with a good record, is what a crash mid-append leaves: a
+// record that runs past the end of the file, or zeros the file was extended with.
+fn is_torn(tail: &[u8]) -> bool {
+    let length = match tail.get(0..4) {
+        Some(length) => u32::from_le_bytes(length.try_into().unwrap()) as usize,
+        None => return true,
+    };
+    HEADER_LEN + length > tail.len() || tail.iter().all(|byte| *byte == 0)
+}
+
+// CRC-32 as used by zip and PNG (reflected polynomial 0xEDB88320).
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_log() -> PathBuf {
+        std::env::temp_dir().join(format!("inverse-pairs-{}.log", Uuid::new_v4()))
+    }
+
+    fn record(sequence: u64) -> LogRecord {
+        LogRecord {
+            sequence,
+            event: Event::new(Uuid::new_v4()),
+        }
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_log_appends_and_reopens() {
+        let path = temp_log();
+        let written = vec![record(1), record(2), record(3)];
+        {
+            let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+            assert!(records.is_empty());
+            for record in &written {
+                log.append(record).unwrap();
+            }
+        }
+
+        let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, written);
+
+        log.compact().unwrap();
+        log.append(&written[0]).unwrap();
+        log.append(&written[1]).unwrap();
+        log.discard_last().unwrap();
+        drop(log);
+        assert_eq!(
+            WriteAheadLog::open(&path).unwrap().1,
+            vec![written[0].clone()]
+        );
+
+        // the last record read back can be discarded too, but only the one
+        let (mut log, _) = WriteAheadLog::open(&path).unwrap();
+        log.discard_last().unwrap();
+        log.discard_last().unwrap();
+        log.append(&written[2]).unwrap();
+        drop(log);
+        assert_eq!(
+            WriteAheadLog::open(&path).unwrap().1,
+            vec![written[2].clone()]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_keeps_event_fields_and_reads_id_only_records() {
+        let path = temp_log();
+        let full = LogRecord {
+            sequence: 7,
+            event: Event::new(Uuid::new_v4())
+                .with_weight(5)
+                .with_timestamp(1_700_000_000_000)
+                .with_payload("tag=a"),
+        };
+        let mut bytes = full.encode();
+
+        // a record written before events had weights
+        let id = Uuid::new_v4();
+        let mut old = vec![ID_ONLY_FORMAT];
+        old.extend_from_slice(&8u64.to_le_bytes());
+        old.extend_from_slice(id.as_bytes());
+        bytes.extend_from_slice(&(old.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&old).to_le_bytes());
+        bytes.extend_from_slice(&old);
+        fs::write(&path, &bytes).unwrap();
+
+        let old = LogRecord {
+            sequence: 8,
+            event: Event::new(id),
+        };
+        assert_eq!(WriteAheadLog::open(&path).unwrap().1, vec![full, old]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_truncates_torn_tail() {
+        let path = temp_log();
+        let written = vec![record(1), record(2)];
+        {
+            let (mut log, _) = WriteAheadLog::open(&path).unwrap();
+            for record in &written {
+                log.append(record).unwrap();
+            }
+        }
+        let intact = fs::metadata(&path).unwrap().len();
+
+        // half of a third record, as if the process died mid-write
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.extend_from_slice(&record(3).encode()[..20]);
+        fs::write(&path, &bytes).unwrap();
+        let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, written);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+
+        // appends after recovery land where the torn record was
+        let next = record(3);
+        log.append(&next).unwrap();
+        drop(log);
+        assert_eq!(
+            WriteAheadLog::open(&path).unwrap().1,
+            vec![written[0].clone(), written[1].clone(), next]
+        );
+
+        // zeros left where the file was extended but never written are cut off too
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(intact as usize);
+        bytes.extend_from_slice(&[0; 64]);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(WriteAheadLog::open(&path).unwrap().1, written);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_refuses_complete_corrupt_records() {
+        let path = temp_log();
+        let written = vec![record(1), record(2), record(3)];
+        {
+            let (mut log, _) = WriteAheadLog::open(&path).unwrap();
+            for record in &written {
+                log.append(record).unwrap();
+            }
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        let second = written[0].encode().len();
+        bytes[second + HEADER_LEN + 3] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        assert!(matches!(
+            WriteAheadLog::open(&path),
+            Err(LogError::Corrupt { offset }) if offset == second as u64
+        ));
+        // and leaves the file as it was for someone to look at
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        // a flipped bit in a complete last record is corruption too, not a torn write
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[second + HEADER_LEN + 3] ^= 1;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let third = (2 * second) as u64;
+        assert!(matches!(
+            WriteAheadLog::open(&path),
+            Err(LogError::Corrupt { offset }) if offset == third
+        ));
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+#![cfg(unix)]
+
+use inverse_pairs::{ClientError, Engine, EngineStats, Event, SocketClient};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// A server running on a socket in a directory of its own, stopped and cleaned up when dropped.
+struct Server {
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Server {
+    fn start(capacity: usize) -> Self {
+        let dir = std::env::temp_dir().join(format!("inverse-pairs-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let server = Server::start_in(dir, capacity);
+        server.wait_until_listening();
+        server
+    }
+
+    fn start_in(dir: PathBuf, capacity: usize) -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_inverse-pairs-socket"))
+            .arg("--capacity")
+            .arg(capacity.to_string())
+            .arg(dir.join("engine.sock"))
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Server { child, dir }
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.dir.join("engine.sock")
+    }
+
+    fn wait_until_listening(&self) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while UnixStream::connect(self.socket()).is_err() {
+            assert!(Instant::now() < deadline, "the server did not start");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn connect(&self) -> SocketClient {
+        SocketClient::connect(self.socket()).unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_ingest_and_get_match_an_engine() {
+    let server = Server::start(4);
+    let mut client = server.connect();
+    let mut engine = Engine::new(4);
+
+    let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+    for (i, id) in ids.iter().chain(&ids[..2]).enumerate() {
+        let event = Event::new(*id)
+            .with_timestamp(i as u64)
+            .with_weight(i as u32 % 3 + 1)
+            .with_payload("payload");
+        assert_eq!(
+            client.ingest(&event).unwrap(),
+            engine.ingest(&event).unwrap()
+        );
+    }
+
+    for id in &ids {
+        assert_eq!(client.get(id).unwrap(), engine.get(id).copied());
+    }
+    assert_eq!(client.get(&Uuid::new_v4()).unwrap(), None);
+}
+
+#[test]
+fn test_stats_and_snapshot() {
+    let server = Server::start(3);
+    let mut client = server.connect();
+    assert_eq!(
+        client.stats().unwrap(),
+        EngineStats {
+            capacity: 3,
+            window: 0,
+            placements: 0,
+            flee_average: None,
+        }
+    );
+
+    let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+    for id in &ids {
+        client.ingest(&Event::new(*id)).unwrap();
+    }
+
+    let stats = client.stats().unwrap();
+    assert_eq!((stats.window, stats.placements), (3, 5));
+
+    // the snapshot restores an engine that carries on exactly like the server's
+    let mut restored = Engine::<2>::from_snapshot(&client.snapshot().unwrap()).unwrap();
+    assert_eq!(stats.flee_average, restored.flee_average());
+    let next = Event::new(Uuid::new_v4());
+    assert_eq!(
+        client.ingest(&next).unwrap(),
+        restored.ingest(&next).unwrap()
+    );
+}
+
+#[test]
+fn test_connections_share_one_engine() {
+    let server = Server::start(64);
+    let clients: Vec<_> = (0..4)
+        .map(|_| {
+            let mut client = server.connect();
+            thread::spawn(move || {
+                let ids: Vec<Uuid> = (0..10).map(|_| Uuid::new_v4()).collect();
+                for id in &ids {
+                    client.ingest(&Event::new(*id)).unwrap();
+                }
+                ids
+            })
+        })
+        .collect();
+    let ids: Vec<Uuid> = clients
+        .into_iter()
+        .flat_map(|client| client.join().unwrap())
+        .collect();
+
+    let mut client = server.connect();
+    assert_eq!(client.stats().unwrap().placements, 40);
+    for id in &ids {
+        assert!(client.get(id).unwrap().is_some());
+    }
+}
+
+#[test]
+fn test_errors_keep_the_connection_open() {
+    let server = Server::start(4);
+
+    // the raw protocol: one line per request, one line per response
+    let stream = UnixStream::connect(server.socket()).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    for request in [
+        "not json",
+        r#"{"command": "compact"}"#,
+        r#"{"command": "stats"}"#,
+    ] {
+        writeln!(writer, "{}", request).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response.get("ok").is_some(), request.contains("stats"));
+    }
+}
+
+#[test]
+fn test_refuses_an_oversized_line() {
+    let server = Server::start(4);
+    let stream = UnixStream::connect(server.socket()).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    // a line over 64 KiB is answered with an error, and the connection is closed
+    let padding = "x".repeat(64 * 1024);
+    writeln!(
+        writer,
+        r#"{{"command": "stats", "padding": "{}"}}"#,
+        padding
+    )
+    .unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(response["error"], "request line is too long");
+    let mut rest = String::new();
+    assert_eq!(reader.read_line(&mut rest).unwrap(), 0);
+
+    // while other connections carry on
+    assert_eq!(server.connect().stats().unwrap().window, 0);
+}
+
+#[test]
+fn test_client_reports_error_responses() {
+    let dir = std::env::temp_dir().join(format!("inverse-pairs-{}", Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+    let listener = UnixListener::bind(dir.join("fake.sock")).unwrap();
+    // a stand-in server that refuses the first request and then stops making sense
+    let fake = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        for response in [r#"{"error": "no room"}"#, r#"{"fine": true}"#] {
+            reader.read_line(&mut String::new()).unwrap();
+            writeln!(writer, "{}", response).unwrap();
+        }
+    });
+
+    let mut client = SocketClient::connect(dir.join("fake.sock")).unwrap();
+    match client.stats() {
+        Err(ClientError::Server(message)) => assert_eq!(message, "no room"),
+        other => panic!("expected a server error, got {:?}", other),
+    }
+    assert!(matches!(client.stats(), Err(ClientError::Protocol(_))));
+    fake.join().unwrap();
+    assert!(matches!(client.stats(), Err(ClientError::Io(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_replaces_a_stale_socket() {
+    let dir = std::env::temp_dir().join(format!("inverse-pairs-{}", Uuid::new_v4()));
+    fs::create_dir(&dir).unwrap();
+    // a socket file whose listener is gone, as a server that was killed leaves behind
+    drop(UnixListener::bind(dir.join("engine.sock")).unwrap());
+
+    let server = Server::start_in(dir, 4);
+    server.wait_until_listening();
+    assert_eq!(server.connect().stats().unwrap().window, 0);
+}